@@ -1,7 +1,12 @@
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, IsTerminal, Write};
 use std::path::Path;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::env;
+
+// ANSI escape codes used to pick out the loop when rendering to a terminal
+const HIGHLIGHT: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[0m";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct Point {
@@ -28,50 +33,39 @@ impl Guard {
         self.dx = new_dx;
         self.dy = new_dy;
     }
- 
+
+    fn facing(&self) -> char {
+        match (self.dx, self.dy) {
+            (0, -1) => 'N',
+            (1, 0) => 'E',
+            (0, 1) => 'S',
+            _ => 'W',
+        }
+    }
+
     fn leaving_grid(&mut self, grid: &[Vec<char>]) -> bool {
         let height = grid.len() as i64 ;
         let width  = grid[0].len() as i64;
         let next_x = self.next_x();
         let next_y = self.next_y();
 
-        return next_x < 0 || next_x >= width || next_y < 0 || next_y >= height;
+        next_x < 0 || next_x >= width || next_y < 0 || next_y >= height
     }
 
     fn next_x(&mut self) -> i64 {
         let current_x = self.pos.x as i64;
         let next_x: i64 = current_x + self.dx;
-        return next_x;
+        next_x
     }
 
     fn next_y(&mut self) -> i64 {
         let current_y = self.pos.y as i64;
         let next_y: i64 = current_y + self.dy;
-        return next_y;
+        next_y
     }
 
-    fn can_move(&mut self, grid: &[Vec<char>]) -> bool {
-        let next_x = self.next_x();
-        let next_y = self.next_y();
-
-        if next_x < 0 || next_y < 0 {
-            return true; // Going off grid is allowed
-        }
-
-        let next_x = next_x as usize;
-        let next_y = next_y as usize;
-
-        if next_y >= grid.len() || next_x >= grid[0].len() {
-            return true; // going off grid is allowed
-        }
-
-        // check grid is clear at the next position
-        return grid[next_y][next_x] != '#';
-
-    }
-
-    fn can_move_obstacle(&mut self, grid: &[Vec<char>], obstacle: &Point) -> bool {
-
+    // obstacle is an extra '#' placed on the grid for part 2
+    fn can_move(&mut self, grid: &[Vec<char>], obstacle: Option<&Point>) -> bool {
         let next_x = self.next_x();
         let next_y = self.next_y();
 
@@ -90,12 +84,11 @@ impl Guard {
             x: next_x,
             y: next_y,
         };
-        if next_pos == *obstacle {
+        if Some(&next_pos) == obstacle {
             return false;
         }
         // check grid is clear at the next position
-        return grid[next_y][next_x] != '#';
-
+        grid[next_y][next_x] != '#'
     }
 
 
@@ -107,6 +100,99 @@ impl Guard {
 
 }
 
+#[derive(Debug, Clone)]
+struct Patrol {
+    // every state the guard was in, one per move or turn
+    steps: Vec<Guard>,
+    // index of the first state the guard came back to, if it got stuck in a loop
+    loop_start: Option<usize>,
+}
+
+impl Patrol {
+    fn positions(&self) -> HashSet<Point> {
+        self.steps.iter().map(|g| g.pos).collect()
+    }
+
+    fn loop_positions(&self) -> HashSet<Point> {
+        match self.loop_start {
+            Some(start) => self.steps[start..].iter().map(|g| g.pos).collect(),
+            None => HashSet::new(),
+        }
+    }
+}
+
+fn patrol(grid: &[Vec<char>], start: Guard, obstacle: Option<&Point>) -> Patrol {
+    let mut steps: Vec<Guard> = vec![start];
+    let mut seen: HashMap<Guard, usize> = HashMap::new();
+    seen.insert(start, 0);
+
+    let mut guard = start;
+    while !guard.leaving_grid(grid) {
+        if guard.can_move(grid, obstacle) {
+            guard.move_step();
+        } else {
+            guard.turn_right();
+        }
+        if let Some(&first) = seen.get(&guard) {
+            return Patrol { steps, loop_start: Some(first) };
+        }
+        seen.insert(guard, steps.len());
+        steps.push(guard);
+    }
+    Patrol { steps, loop_start: None }
+}
+
+// Draw the patrol the same way as the puzzle examples:
+// | for vertical movement, - for horizontal and + where the guard turned or crossed
+// and the highlighted cells in colour, if colour is set
+fn render_patrol(grid: &[Vec<char>], patrol: &Patrol, obstacle: Option<&Point>, highlight: &HashSet<Point>, colour: bool) -> String {
+    let mut vertical: HashSet<Point> = HashSet::new();
+    let mut horizontal: HashSet<Point> = HashSet::new();
+    for g in &patrol.steps {
+        if g.dx == 0 {
+            vertical.insert(g.pos);
+        } else {
+            horizontal.insert(g.pos);
+        }
+    }
+
+    let mut output = String::new();
+    for (row_idx, row) in grid.iter().enumerate() {
+        for (col_idx, ch) in row.iter().enumerate() {
+            let p = Point { x: col_idx, y: row_idx };
+            let symbol = if Some(&p) == obstacle {
+                'O'
+            } else if *ch != '.' {
+                *ch
+            } else {
+                match (vertical.contains(&p), horizontal.contains(&p)) {
+                    (true, true) => '+',
+                    (true, false) => '|',
+                    (false, true) => '-',
+                    (false, false) => '.',
+                }
+            };
+            if colour && (highlight.contains(&p) || (Some(&p) == obstacle && !highlight.is_empty())) {
+                output.push_str(HIGHLIGHT);
+                output.push(symbol);
+                output.push_str(RESET);
+            } else {
+                output.push(symbol);
+            }
+        }
+        output.push('\n');
+    }
+    output
+}
+
+fn write_trace(path: &Path, patrol: &Patrol) -> io::Result<()> {
+    let mut file = io::BufWriter::new(File::create(path)?);
+    writeln!(file, "step,x,y,facing")?;
+    for (step, g) in patrol.steps.iter().enumerate() {
+        writeln!(file, "{},{},{},{}", step, g.pos.x, g.pos.y, g.facing())?;
+    }
+    file.flush()
+}
 
 fn find_guard(grid: &[Vec<char>]) -> Option<Guard> {
     for (row_idx, row) in grid.iter().enumerate() {
@@ -126,10 +212,24 @@ fn find_guard(grid: &[Vec<char>]) -> Option<Guard> {
 
 fn main() -> io::Result<()> {
 
+    // Options:
+    //   --render        draw the guard's patrol
+    //   --loops         draw every loop caused by a new obstacle
+    //   --trace <file>  export each step of the patrol as CSV
+    let args: Vec<String> = env::args().skip(1).collect();
+    let render = args.iter().any(|a| a == "--render");
+    let render_loops = args.iter().any(|a| a == "--loops");
+    let trace_path = match args.iter().position(|a| a == "--trace") {
+        Some(i) => Some(args.get(i + 1).filter(|p| !p.starts_with("--")).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--trace needs a file"))?),
+        None => None,
+    };
+    // only colour the loops when they'll be seen in a terminal
+    let colour = io::stdout().is_terminal();
+
     // Open the file
     let path = Path::new("input");
     //let path = Path::new("sample");
-    let file = File::open(&path)?;
+    let file = File::open(path)?;
     let reader = io::BufReader::new(file);
 
     let mut part1_answer: u64 = 0;
@@ -139,45 +239,33 @@ fn main() -> io::Result<()> {
     // Process each line
     let grid: Vec<Vec<char>> = reader.lines().map(|line| line.expect("Could not read line").chars().collect()).collect();
 
-    let height: usize = grid.len();
-    let width: usize = grid[0].len();
-    let mut visited: HashMap<Point, u64> = HashMap::new();
+    if let Some(original_guard) = find_guard(&grid) {
+        let route = patrol(&grid, original_guard, None);
+        let visited = route.positions();
+        part1_answer = visited.len() as u64;
 
-    if let Some(mut guard) = find_guard(&grid) {
-        visited.insert(guard.pos, 1);
-        while !guard.leaving_grid(&grid) {
-            if guard.can_move(&grid) {
-                guard.move_step();
-                *visited.entry(guard.pos).or_insert(0) += 1;
-            } else {
-                guard.turn_right();
-            }
+        if render {
+            print!("{}", render_patrol(&grid, &route, None, &HashSet::new(), colour));
+        }
+        if let Some(trace_path) = trace_path {
+            write_trace(Path::new(trace_path), &route)?;
+            println!("Wrote {} steps to {}", route.steps.len(), trace_path);
         }
-    }
-    part1_answer = visited.len() as u64;
-    
-
-    // Get the guard back at the original location
-    if let Some(original_guard) = find_guard(&grid) {
-        for obstacle_position in visited.keys() {
-            let mut travelled: HashMap<Guard, u64> = HashMap::new();
-            let mut guard = original_guard.clone();
-            travelled.insert(guard, 1);
 
+        // Try an obstacle at each position the guard visits, in reading order
+        let mut candidates: Vec<Point> = visited.into_iter().collect();
+        candidates.sort_by_key(|p| (p.y, p.x));
+        for obstacle_position in candidates {
             // Don't add an obstacle where the guard starts
-            if *obstacle_position == original_guard.pos {
+            if obstacle_position == original_guard.pos {
                 continue;
             }
-            while !guard.leaving_grid(&grid) {
-                if guard.can_move_obstacle(&grid, &obstacle_position) {
-                    guard.move_step();
-                    if travelled.contains_key(&guard) {
-                        part2_answer += 1;
-                        break;
-                    }
-                    travelled.insert(guard, 1);
-                } else {
-                    guard.turn_right();
+            let obstructed = patrol(&grid, original_guard, Some(&obstacle_position));
+            if obstructed.loop_start.is_some() {
+                part2_answer += 1;
+                if render_loops {
+                    println!("Loop {} with obstacle at {:?}", part2_answer, obstacle_position);
+                    print!("{}", render_patrol(&grid, &obstructed, Some(&obstacle_position), &obstructed.loop_positions(), colour));
                 }
             }
         }
@@ -188,4 +276,3 @@ fn main() -> io::Result<()> {
 
     Ok(())
 }
-
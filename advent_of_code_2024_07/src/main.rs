@@ -1,121 +1,563 @@
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
-use itertools::Itertools;
-
-fn generate_operator_vec(num_operators: usize) -> Vec<Vec<char>> {
-    let mut results = Vec::new();
-
-    for i in 0..(1 << num_operators) {
-        let mut operators = Vec::new();
-        
-        for j in 0..num_operators {
-            if (i & (1 << j)) != 0 {
-                operators.push('*');
-            } else {
-                operators.push('+');
+use std::collections::{HashMap, HashSet};
+use std::env;
+
+// What undoing an operator tells us about its left operand
+enum Inverse {
+    // only these left operands give the result
+    Values(Vec<u128>),
+    // any left operand gives the result, e.g. x * 0 = 0
+    Any,
+}
+
+trait Operator {
+    fn symbol(&self) -> String;
+
+    // None if the result isn't a valid u128 (overflow, negative, divide by zero)
+    fn apply(&self, left: u128, right: u128) -> Option<u128>;
+
+    // Operators that can be undone let the equation be solved backwards from
+    // the test value. If any operator in the set can't, we solve forwards instead.
+    fn invertible(&self) -> bool {
+        false
+    }
+
+    fn inverse(&self, _result: u128, _right: u128) -> Inverse {
+        Inverse::Values(Vec::new())
+    }
+}
+
+struct Add;
+struct Multiply;
+struct Subtract;
+struct Divide;
+struct Exponent;
+struct Concatenate {
+    base: u128,
+}
+
+impl Operator for Add {
+    fn symbol(&self) -> String {
+        "+".to_string()
+    }
+
+    fn apply(&self, left: u128, right: u128) -> Option<u128> {
+        left.checked_add(right)
+    }
+
+    fn invertible(&self) -> bool {
+        true
+    }
+
+    fn inverse(&self, result: u128, right: u128) -> Inverse {
+        Inverse::Values(result.checked_sub(right).into_iter().collect())
+    }
+}
+
+impl Operator for Multiply {
+    fn symbol(&self) -> String {
+        "*".to_string()
+    }
+
+    fn apply(&self, left: u128, right: u128) -> Option<u128> {
+        left.checked_mul(right)
+    }
+
+    fn invertible(&self) -> bool {
+        true
+    }
+
+    fn inverse(&self, result: u128, right: u128) -> Inverse {
+        match right {
+            0 if result == 0 => Inverse::Any,
+            0 => Inverse::Values(Vec::new()),
+            _ if result.is_multiple_of(right) => Inverse::Values(vec![result / right]),
+            _ => Inverse::Values(Vec::new()),
+        }
+    }
+}
+
+// Results must stay non-negative, so a - b with b > a is not a solution
+impl Operator for Subtract {
+    fn symbol(&self) -> String {
+        "-".to_string()
+    }
+
+    fn apply(&self, left: u128, right: u128) -> Option<u128> {
+        left.checked_sub(right)
+    }
+
+    fn invertible(&self) -> bool {
+        true
+    }
+
+    fn inverse(&self, result: u128, right: u128) -> Inverse {
+        Inverse::Values(result.checked_add(right).into_iter().collect())
+    }
+}
+
+// Integer division, rounding down. Undoing it gives a whole range of left
+// operands so it is only solved forwards.
+impl Operator for Divide {
+    fn symbol(&self) -> String {
+        "/".to_string()
+    }
+
+    fn apply(&self, left: u128, right: u128) -> Option<u128> {
+        left.checked_div(right)
+    }
+}
+
+impl Operator for Exponent {
+    fn symbol(&self) -> String {
+        "^".to_string()
+    }
+
+    fn apply(&self, left: u128, right: u128) -> Option<u128> {
+        match left {
+            0 | 1 if right > 0 => Some(left),
+            _ => left.checked_pow(u32::try_from(right).ok()?),
+        }
+    }
+
+    fn invertible(&self) -> bool {
+        true
+    }
+
+    fn inverse(&self, result: u128, right: u128) -> Inverse {
+        if right == 0 {
+            return if result == 1 { Inverse::Any } else { Inverse::Values(Vec::new()) };
+        }
+        let roots = integer_root(result, right).into_iter().collect();
+        Inverse::Values(roots)
+    }
+}
+
+// The whole number whose nth power is value, if there is one.
+// Binary search so large u128 values don't lose precision through floats.
+fn integer_root(value: u128, n: u128) -> Option<u128> {
+    if value <= 1 || n == 1 {
+        return Some(value);
+    }
+    let exponent = u32::try_from(n).ok()?;
+    let (mut low, mut high) = (0u128, value.min(u64::MAX as u128) + 1);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        match mid.checked_pow(exponent) {
+            Some(power) if power == value => return Some(mid),
+            Some(power) if power < value => low = mid + 1,
+            _ => high = mid,
+        }
+    }
+    None
+}
+
+impl Concatenate {
+    // Smallest power of the base greater than value, i.e. what a || b shifts a by.
+    // None if that power doesn't fit in a u128.
+    fn magnitude(&self, value: u128) -> Option<u128> {
+        let mut magnitude: u128 = self.base;
+        while magnitude <= value {
+            magnitude = magnitude.checked_mul(self.base)?;
+        }
+        Some(magnitude)
+    }
+}
+
+impl Operator for Concatenate {
+    fn symbol(&self) -> String {
+        if self.base == 10 { "||".to_string() } else { format!("||{}", self.base) }
+    }
+
+    fn apply(&self, left: u128, right: u128) -> Option<u128> {
+        left.checked_mul(self.magnitude(right)?)?.checked_add(right)
+    }
+
+    fn invertible(&self) -> bool {
+        true
+    }
+
+    fn inverse(&self, result: u128, right: u128) -> Inverse {
+        match self.magnitude(right) {
+            Some(magnitude) if result % magnitude == right => Inverse::Values(vec![result / magnitude]),
+            _ => Inverse::Values(Vec::new()),
+        }
+    }
+}
+
+// Operators are given as a comma separated list, e.g. "+,*,||" or "+,-,^,||2"
+// where ||N concatenates digits in base N.
+fn parse_operators(list: &str) -> Result<Vec<Box<dyn Operator>>, String> {
+    list.split(',').map(|s| {
+        let s = s.trim();
+        let operator: Box<dyn Operator> = match s {
+            "+" => Box::new(Add),
+            "*" => Box::new(Multiply),
+            "-" => Box::new(Subtract),
+            "/" => Box::new(Divide),
+            "^" => Box::new(Exponent),
+            "||" => Box::new(Concatenate { base: 10 }),
+            _ => match s.strip_prefix("||").and_then(|b| b.parse::<u128>().ok()) {
+                Some(base) if base >= 2 => Box::new(Concatenate { base }),
+                _ => return Err(format!("Unknown operator '{}'", s)),
+            },
+        };
+        Ok(operator)
+    }).collect()
+}
+
+fn part1_operators() -> Vec<Box<dyn Operator>> {
+    vec![Box::new(Add), Box::new(Multiply)]
+}
+
+fn part2_operators() -> Vec<Box<dyn Operator>> {
+    vec![Box::new(Add), Box::new(Multiply), Box::new(Concatenate { base: 10 })]
+}
+
+// Work backwards from the test value, undoing the last operator at each step.
+// Any branch where the undo isn't possible is pruned straight away rather
+// than evaluating every combination.
+// Returns the operators (left to right, as indexes into operators) of the first equation found.
+// dead_ends remembers (values remaining, target) pairs already known to fail
+// so long equations with lots of ways to reach the same target stay fast.
+fn solve_backwards(
+    test_value: u128,
+    equation_values: &[u128],
+    operators: &[Box<dyn Operator>],
+    dead_ends: &mut HashSet<(usize, u128)>,
+) -> Option<Vec<usize>> {
+    let (&last, rest) = equation_values.split_last()?;
+    if rest.is_empty() {
+        return if test_value == last { Some(Vec::new()) } else { None };
+    }
+    if dead_ends.contains(&(equation_values.len(), test_value)) {
+        return None;
+    }
+
+    for (op_idx, operator) in operators.iter().enumerate() {
+        let found = match operator.inverse(test_value, last) {
+            Inverse::Values(lefts) => lefts.into_iter().find_map(|left| solve_backwards(left, rest, operators, dead_ends)),
+            // the rest of the equation just has to evaluate to something
+            Inverse::Any => solve_forwards(rest[0], &rest[1..], None, operators, &mut HashSet::new()),
+        };
+        if let Some(mut found_operators) = found {
+            found_operators.push(op_idx);
+            return Some(found_operators);
+        }
+    }
+
+    dead_ends.insert((equation_values.len(), test_value));
+    None
+}
+
+// Work forwards from the first value, used when an operator can't be undone.
+// A target of None accepts any result.
+fn solve_forwards(
+    current: u128,
+    remaining_values: &[u128],
+    test_value: Option<u128>,
+    operators: &[Box<dyn Operator>],
+    dead_ends: &mut HashSet<(usize, u128)>,
+) -> Option<Vec<usize>> {
+    let Some((&next, rest)) = remaining_values.split_first() else {
+        return if test_value.is_none_or(|t| t == current) { Some(Vec::new()) } else { None };
+    };
+    if dead_ends.contains(&(remaining_values.len(), current)) {
+        return None;
+    }
+
+    for (op_idx, operator) in operators.iter().enumerate() {
+        if let Some(result) = operator.apply(current, next) {
+            if let Some(mut found_operators) = solve_forwards(result, rest, test_value, operators, dead_ends) {
+                found_operators.insert(0, op_idx);
+                return Some(found_operators);
             }
         }
-        results.push(operators)
     }
-    results
+
+    dead_ends.insert((remaining_values.len(), current));
+    None
 }
 
-fn generate_operator_concatenator_vec(num_operators: usize) -> Vec<Vec<char>> {
-    let operators = "+*|".chars().collect::<Vec<_>>();
+// Number of operator assignments that make the equation equal the test value.
+// memo holds the count for (values remaining, target) pairs already worked out.
+// Counts saturate at u128::MAX rather than overflowing.
+fn count_backwards(
+    test_value: u128,
+    equation_values: &[u128],
+    operators: &[Box<dyn Operator>],
+    memo: &mut HashMap<(usize, u128), u128>,
+) -> u128 {
+    let Some((&last, rest)) = equation_values.split_last() else {
+        return 0;
+    };
+    if rest.is_empty() {
+        return if test_value == last { 1 } else { 0 };
+    }
+    if let Some(&count) = memo.get(&(equation_values.len(), test_value)) {
+        return count;
+    }
 
-    let results: Vec<Vec<char>> = std::iter::repeat(operators.iter().copied()) // convert to char from &char
-        .take(num_operators)
-        .multi_cartesian_product()
-        .collect();
+    let mut count: u128 = 0;
+    for operator in operators {
+        let ways = match operator.inverse(test_value, last) {
+            Inverse::Values(lefts) => lefts.into_iter()
+                .fold(0u128, |total, left| total.saturating_add(count_backwards(left, rest, operators, memo))),
+            Inverse::Any => count_forwards(rest[0], &rest[1..], None, operators, &mut HashMap::new()),
+        };
+        count = count.saturating_add(ways);
+    }
 
-    //println!("Generated operators {:?}", results);
-    results
+    memo.insert((equation_values.len(), test_value), count);
+    count
 }
 
-fn find_equation_combinations(test_value: u64, equation_values: Vec<u64>, part2: bool) -> bool {
-    if equation_values.is_empty() {
-        return false;
+fn count_forwards(
+    current: u128,
+    remaining_values: &[u128],
+    test_value: Option<u128>,
+    operators: &[Box<dyn Operator>],
+    memo: &mut HashMap<(usize, u128), u128>,
+) -> u128 {
+    let Some((&next, rest)) = remaining_values.split_first() else {
+        return if test_value.is_none_or(|t| t == current) { 1 } else { 0 };
+    };
+    if let Some(&count) = memo.get(&(remaining_values.len(), current)) {
+        return count;
     }
 
-    // Number of operators is one less than the number of equation values
-    let num_operators = equation_values.len() - 1;
+    let mut count: u128 = 0;
+    for operator in operators {
+        if let Some(result) = operator.apply(current, next) {
+            count = count.saturating_add(count_forwards(result, rest, test_value, operators, memo));
+        }
+    }
 
-    let combinations = if part2 { generate_operator_concatenator_vec(num_operators) } else { generate_operator_vec(num_operators) };
-    for operator_combination in combinations {
+    memo.insert((remaining_values.len(), current), count);
+    count
+}
 
-        // Evaluate the equation for this combination of operators
-        let mut result = equation_values[0] as i64;
-        let mut equation = format!("{}", result);
-        //println!("Operator Combinations: {:?}", operator_combination);
+// Collect up to limit operator assignments that work, skipping any branch
+// the counts say has no solutions. suffix holds the operators chosen so far
+// from the right hand end.
+#[allow(clippy::too_many_arguments)]
+fn list_backwards(
+    test_value: u128,
+    equation_values: &[u128],
+    operators: &[Box<dyn Operator>],
+    memo: &mut HashMap<(usize, u128), u128>,
+    suffix: &mut Vec<usize>,
+    limit: usize,
+    found: &mut Vec<Vec<usize>>,
+) {
+    let Some((&last, rest)) = equation_values.split_last() else {
+        return;
+    };
+    if found.len() >= limit {
+        return;
+    }
+    if rest.is_empty() {
+        if test_value == last {
+            found.push(suffix.iter().rev().copied().collect());
+        }
+        return;
+    }
+    if count_backwards(test_value, equation_values, operators, memo) == 0 {
+        return;
+    }
 
-        for (&value, &operator) in equation_values.iter().skip(1).zip(operator_combination.iter()) {
-            match operator {
-                '+' => result += value as i64,
-                '*' => result *= value as i64,
-                '|' => result = concatenate(result as u64, value).unwrap_or(0) as i64,
-                _ => unreachable!(),
+    for (op_idx, operator) in operators.iter().enumerate() {
+        suffix.push(op_idx);
+        match operator.inverse(test_value, last) {
+            Inverse::Values(lefts) => {
+                for left in lefts {
+                    list_backwards(left, rest, operators, memo, suffix, limit, found);
+                }
             }
-            equation.push_str(&format!(" {} {}", operator, value));
-            if result > test_value as i64 {
-                break;
+            Inverse::Any => {
+                let mut prefixes: Vec<Vec<usize>> = Vec::new();
+                let remaining_limit = limit.saturating_sub(found.len());
+                list_forwards(rest[0], &rest[1..], None, operators, &mut HashMap::new(), &mut Vec::new(), remaining_limit, &mut prefixes);
+                for mut prefix in prefixes {
+                    prefix.extend(suffix.iter().rev());
+                    found.push(prefix);
+                }
             }
         }
+        suffix.pop();
+    }
+}
 
-        // Check if the result matches the test value
-        if result == test_value as i64 {
-            println!("Matching equation: {} = {}", equation, test_value);
-            return true
-        } else {
-            //println!("Not Matching equation: {}", equation);
+#[allow(clippy::too_many_arguments)]
+fn list_forwards(
+    current: u128,
+    remaining_values: &[u128],
+    test_value: Option<u128>,
+    operators: &[Box<dyn Operator>],
+    memo: &mut HashMap<(usize, u128), u128>,
+    prefix: &mut Vec<usize>,
+    limit: usize,
+    found: &mut Vec<Vec<usize>>,
+) {
+    if found.len() >= limit {
+        return;
+    }
+    let Some((&next, rest)) = remaining_values.split_first() else {
+        if test_value.is_none_or(|t| t == current) {
+            found.push(prefix.clone());
         }
-            
+        return;
+    };
+    if count_forwards(current, remaining_values, test_value, operators, memo) == 0 {
+        return;
     }
 
-    false
+    for (op_idx, operator) in operators.iter().enumerate() {
+        if let Some(result) = operator.apply(current, next) {
+            prefix.push(op_idx);
+            list_forwards(result, rest, test_value, operators, memo, prefix, limit, found);
+            prefix.pop();
+        }
+    }
 }
 
-fn concatenate(a: u64, b:u64) -> Option<u64> {
-    let concatenated_value = format!("{}{}", a, b);
-    concatenated_value.parse::<u64>().ok()
+// Count every operator assignment that works and list up to limit of them
+fn find_all_equations(test_value: u128, equation_values: &[u128], operators: &[Box<dyn Operator>], limit: usize) -> (u128, Vec<Vec<usize>>) {
+    let mut found: Vec<Vec<usize>> = Vec::new();
+    let mut memo: HashMap<(usize, u128), u128> = HashMap::new();
+    if equation_values.is_empty() {
+        return (0, found);
+    }
+
+    let count = if operators.iter().all(|op| op.invertible()) {
+        list_backwards(test_value, equation_values, operators, &mut memo, &mut Vec::new(), limit, &mut found);
+        count_backwards(test_value, equation_values, operators, &mut memo)
+    } else {
+        list_forwards(equation_values[0], &equation_values[1..], Some(test_value), operators, &mut memo, &mut Vec::new(), limit, &mut found);
+        count_forwards(equation_values[0], &equation_values[1..], Some(test_value), operators, &mut memo)
+    };
+    (count, found)
+}
+
+fn format_equation(equation_values: &[u128], found_operators: &[usize], operators: &[Box<dyn Operator>]) -> String {
+    let mut equation = format!("{}", equation_values[0]);
+    for (&value, &op_idx) in equation_values.iter().skip(1).zip(found_operators.iter()) {
+        equation.push_str(&format!(" {} {}", operators[op_idx].symbol(), value));
+    }
+    equation
+}
+
+fn find_equation_combinations(test_value: u128, equation_values: &[u128], operators: &[Box<dyn Operator>]) -> bool {
+    if equation_values.is_empty() {
+        return false;
+    }
+
+    let solution = if operators.iter().all(|op| op.invertible()) {
+        solve_backwards(test_value, equation_values, operators, &mut HashSet::new())
+    } else {
+        solve_forwards(equation_values[0], &equation_values[1..], Some(test_value), operators, &mut HashSet::new())
+    };
+
+    if let Some(found_operators) = solution {
+        println!("Matching equation: {} = {}", format_equation(equation_values, &found_operators, operators), test_value);
+        return true;
+    }
+
+    false
 }
 
 
 fn main() -> io::Result<()> {
 
+    // Options:
+    //   --operators <list>  also solve with a custom operator set, e.g. "+,*,-,^,||2"
+    //   --count             count every working operator assignment (custom set, or part 2 set)
+    //   --limit <n>         list at most n assignments per equation when counting (default 100)
+    let args: Vec<String> = env::args().skip(1).collect();
+    let count_mode = args.iter().any(|a| a == "--count");
+    let limit: usize = args.iter().position(|a| a == "--limit")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(100);
+    let custom_operators = match args.iter().position(|a| a == "--operators").and_then(|i| args.get(i + 1)) {
+        Some(list) => Some(parse_operators(list).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?),
+        None => None,
+    };
 
     // Open the file
     let path = Path::new("input");
     //let path = Path::new("sample");
-    let file = File::open(&path)?;
+    let file = File::open(path)?;
     let reader = io::BufReader::new(file);
 
-    let mut part1_answer: u64 = 0;
+    let mut part1_answer: u128 = 0;
+
+    let mut part2_answer: u128 = 0;
+
+    let mut custom_answer: u128 = 0;
 
-    let mut part2_answer: u64 = 0;
+    let mut total_solutions: u128 = 0;
 
+    let part1 = part1_operators();
+    let part2 = part2_operators();
 
     // Process each line
     for line in reader.lines() {
         let line = line?;
-        if let Some((test_value_str, equation_string)) = line.split_once(": ") { 
-            let test_value: u64 = test_value_str.parse().unwrap();
-            let equation_values: Vec<u64> = equation_string.split_whitespace().filter_map(|s| s.parse::<u64>().ok()).collect();
-            let p2_equation_values: Vec<u64> = equation_values.clone();
+        if let Some((test_value_str, equation_string)) = line.split_once(": ") {
+            let test_value = test_value_str.parse::<u128>();
+            let equation_values = equation_string.split_whitespace().map(|s| s.parse::<u128>()).collect::<Result<Vec<u128>, _>>();
+            let (Ok(test_value), Ok(equation_values)) = (test_value, equation_values) else {
+                println!("Skipping equation that doesn't fit in a u128: {}", line);
+                continue;
+            };
 
-            if find_equation_combinations(test_value, equation_values, false) {
-                part1_answer += test_value
+            if find_equation_combinations(test_value, &equation_values, &part1) {
+                part1_answer = part1_answer.saturating_add(test_value)
             }
 
-            if find_equation_combinations(test_value, p2_equation_values, true) {
-                part2_answer += test_value
+            if find_equation_combinations(test_value, &equation_values, &part2) {
+                part2_answer = part2_answer.saturating_add(test_value)
+            }
+
+            if let Some(operators) = &custom_operators {
+                if find_equation_combinations(test_value, &equation_values, operators) {
+                    custom_answer = custom_answer.saturating_add(test_value)
+                }
+            }
+
+            if count_mode {
+                let operators = custom_operators.as_ref().unwrap_or(&part2);
+                let (count, found) = find_all_equations(test_value, &equation_values, operators, limit);
+                if count > 0 {
+                    println!("{}: {} solutions", test_value, count);
+                    for found_operators in &found {
+                        println!("  {} = {}", format_equation(&equation_values, found_operators, operators), test_value);
+                    }
+                    if count > found.len() as u128 {
+                        println!("  ... and {} more", count - found.len() as u128);
+                    }
+                }
+                total_solutions = total_solutions.saturating_add(count);
             }
 
         }
     }
+    // totals saturate rather than wrap if the test values are enormous
+    if [part1_answer, part2_answer, custom_answer].contains(&u128::MAX) {
+        println!("Warning: a total overflowed u128 and is capped at u128::MAX");
+    }
     println!("Part1: {:?}", part1_answer);
     println!("Part2: {:?}", part2_answer);
+    if let Some(operators) = &custom_operators {
+        let symbols: Vec<String> = operators.iter().map(|op| op.symbol()).collect();
+        println!("Custom ({}): {:?}", symbols.join(" "), custom_answer);
+    }
+    if count_mode {
+        println!("Total solutions: {}", total_solutions);
+    }
 
     Ok(())
 }
-
@@ -1,7 +1,8 @@
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Write};
 use std::path::Path;
 use std::collections::HashMap;
+use std::env;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct Point {
@@ -30,6 +31,22 @@ struct Distance {
     y: isize,
 }
 
+impl Distance {
+    // The smallest step along the same line, so grid points in between aren't skipped
+    fn reduced(self) -> (Distance, isize) {
+        let divisor = gcd(self.x.abs(), self.y.abs()).max(1);
+        (Distance { x: self.x / divisor, y: self.y / divisor }, divisor)
+    }
+
+    fn scaled(self, multiple: isize) -> Distance {
+        Distance { x: self.x * multiple, y: self.y * multiple }
+    }
+}
+
+fn gcd(a: isize, b: isize) -> isize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct Antinode {
     position: Point,
@@ -37,13 +54,46 @@ struct Antinode {
     b: Antenna,
 }
 
+// Which multiples of the antenna separation, measured outwards from each
+// antenna, are antinodes. 1 is the part 1 antinode, 0 is the antenna itself
+// and negative multiples head back past the other antenna. None is unbounded.
+// Fractions of the separation are included wherever they land on the grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Harmonics {
+    min: Option<isize>,
+    max: Option<isize>,
+}
+
+impl Harmonics {
+    const PART1: Harmonics = Harmonics { min: Some(1), max: Some(1) };
+    const PART2: Harmonics = Harmonics { min: None, max: None };
+
+    // "MIN..MAX" with either end optional, e.g. "1..3", "0..", "..", or a single "N"
+    fn parse(s: &str) -> Option<Harmonics> {
+        let bound = |b: &str| if b.is_empty() { Some(None) } else { b.parse::<isize>().ok().map(Some) };
+        match s.split_once("..") {
+            Some((min, max)) => Some(Harmonics { min: bound(min)?, max: bound(max)? }),
+            None => {
+                let n = s.parse::<isize>().ok()?;
+                Some(Harmonics { min: Some(n), max: Some(n) })
+            }
+        }
+    }
+
+    // Whether t steps of size 1/steps_per_multiple of the separation lies in range
+    fn contains(&self, t: isize, steps_per_multiple: isize) -> bool {
+        self.min.is_none_or(|min| t >= min * steps_per_multiple)
+            && self.max.is_none_or(|max| t <= max * steps_per_multiple)
+    }
+}
+
 fn antinode_position(a: &Point, d: &Distance, max: &Point) -> Option<Point> {
     let new_x = a.x as isize + d.x;
     let new_y = a.y as isize + d.y;
     if new_x < 0 || new_x > max.x as isize || new_y < 0 || new_y > max.y as isize {
         return None
     }
-    return Some(Point{ x: new_x as usize, y: new_y as usize });
+    Some(Point{ x: new_x as usize, y: new_y as usize })
 }
 
 // Function to print the grid
@@ -57,15 +107,15 @@ fn print_grid(
     for y in 0..=max_y {
         for x in 0..=max_x {
             let pos = Point { x, y };
-            
+
             // Print antenna if it exists
             if let Some(&frequency) = antennas.get(&pos) {
                 print!("{}", frequency);
-            } 
+            }
             // Print antinode if no antenna exists
             else if antinodes.contains_key(&pos) {
                 print!("#");
-            } 
+            }
             // Print empty space if nothing exists
             else {
                 print!(".");
@@ -75,46 +125,356 @@ fn print_grid(
     }
 }
 
-fn find_antinodes_p2 (a: &Point, d: &Distance, max: &Point) -> Option<Vec<Point>> {
-    let mut points: Vec<Point> = Vec::new();
+// Antinodes produced on the far side of a from b (and back past b for
+// negative multiples), stepping by the gcd reduced separation so every grid
+// point on the line is found.
+fn find_antinodes(a: &Antenna, b: &Antenna, harmonics: &Harmonics, max: &Point) -> Vec<Antinode> {
+    let mut antinodes: Vec<Antinode> = Vec::new();
+    let (step, steps_per_multiple) = a.distance_to(b).reduced();
 
-    let mut pos = *a;
-    points.push(pos);
-    let mut distance = *d;
+    // Walk outwards from a, then back the other way, until we leave the grid
+    // or the range. t counts steps from a, so t / steps_per_multiple is the multiple.
+    for direction in [1, -1] {
+        let mut t: isize = if direction == 1 { 0 } else { -1 };
+        while let Some(position) = antinode_position(&a.position, &step.scaled(t), max) {
+            let past_max = direction == 1 && harmonics.max.is_some_and(|max| t > max * steps_per_multiple);
+            let past_min = direction == -1 && harmonics.min.is_some_and(|min| t < min * steps_per_multiple);
+            if past_max || past_min {
+                break;
+            }
+            if harmonics.contains(t, steps_per_multiple) {
+                antinodes.push(Antinode { position, a: *a, b: *b });
+            }
+            t += direction;
+        }
+    }
+    antinodes
+}
+
+fn find_all_antinodes(antenna_map: &HashMap<char, Vec<Antenna>>, harmonics: &Harmonics, max: &Point) -> Vec<Antinode> {
+    let mut antinodes: Vec<Antinode> = Vec::new();
+
+    let mut frequencies: Vec<&char> = antenna_map.keys().collect();
+    frequencies.sort();
+    for frequency in frequencies {
+        let antennas = &antenna_map[frequency];
+        for i in 0..antennas.len() {
+            for j in i + 1..antennas.len() {
+                let a = &antennas[i];
+                let b = &antennas[j];
+                let mut pair_antinodes = find_antinodes(a, b, harmonics, max);
+                pair_antinodes.extend(find_antinodes(b, a, harmonics, max));
+                // with unbounded ranges both walks cover the whole line
+                let mut seen: HashMap<Point, usize> = HashMap::new();
+                pair_antinodes.retain(|an| seen.insert(an.position, 1).is_none());
+                antinodes.extend(pair_antinodes);
+            }
+        }
+    }
+    antinodes
+}
 
-    while let Some(antinode_pos) = antinode_position(&pos, &distance, max) {
-        points.push(antinode_pos);
-        pos = antinode_pos;
+fn antinode_locations(antinodes: &[Antinode]) -> HashMap<Point, usize> {
+    let mut locations: HashMap<Point, usize> = HashMap::new();
+    for an in antinodes {
+        *locations.entry(an.position).or_insert(0) += 1;
     }
+    locations
+}
+
+// Every antinode cell produced by one frequency, in reading order
+fn antinodes_for_frequency(antinodes: &[Antinode], frequency: char) -> Vec<Point> {
+    let mut cells: Vec<Point> = antinodes.iter()
+        .filter(|an| an.a.frequency == frequency)
+        .map(|an| an.position)
+        .collect();
+    cells.sort_by_key(|p| (p.y, p.x));
+    cells.dedup();
+    cells
+}
+
+// The antenna pairs that produce an antinode at a cell
+fn pairs_producing(antinodes: &[Antinode], cell: &Point) -> Vec<(Antenna, Antenna)> {
+    antinodes.iter()
+        .filter(|an| an.position == *cell)
+        .map(|an| (an.a, an.b))
+        .collect()
+}
 
-    // reverse distance and search other direction
-    distance = Distance{ x: d.x * -1, y: d.y * -1};
-    // Go back to original position
-    pos = *a;
-    while let Some(antinode_pos) = antinode_position(&pos, &distance, max) {
-        points.push(antinode_pos);
-        pos = antinode_pos;
+// The frequencies with an antinode at each cell, in reading order
+fn frequencies_by_cell(antinodes: &[Antinode]) -> Vec<(Point, Vec<char>)> {
+    let mut by_cell: HashMap<Point, Vec<char>> = HashMap::new();
+    for an in antinodes {
+        let frequencies = by_cell.entry(an.position).or_default();
+        if !frequencies.contains(&an.a.frequency) {
+            frequencies.push(an.a.frequency);
+        }
     }
-    if points.len() > 0 {
-        return Some(points);
+    let mut cells: Vec<(Point, Vec<char>)> = by_cell.into_iter().collect();
+    for (_, frequencies) in cells.iter_mut() {
+        frequencies.sort();
     }
-    None
+    cells.sort_by_key(|(p, _)| (p.y, p.x));
+    cells
+}
+
+// Cells where antinodes of more than one frequency overlap
+fn overlapping_antinodes(antinodes: &[Antinode]) -> Vec<(Point, Vec<char>)> {
+    frequencies_by_cell(antinodes).into_iter()
+        .filter(|(_, frequencies)| frequencies.len() > 1)
+        .collect()
+}
 
+// Antinodes that land on an antenna, with that antenna's frequency
+fn antinodes_on_antennas(antinodes: &[Antinode], antennas: &HashMap<Point, char>) -> Vec<(Point, char, Vec<char>)> {
+    frequencies_by_cell(antinodes).into_iter()
+        .filter_map(|(p, frequencies)| antennas.get(&p).map(|&antenna| (p, antenna, frequencies)))
+        .collect()
+}
+
+// Colour for each frequency, spreading the hues round the colour wheel
+fn frequency_colours(antenna_map: &HashMap<char, Vec<Antenna>>) -> HashMap<char, [u8; 3]> {
+    let mut frequencies: Vec<&char> = antenna_map.keys().collect();
+    frequencies.sort();
+    frequencies.into_iter().enumerate().map(|(i, &frequency)| {
+        let hue = (i as f64 * 137.508) % 360.0;
+        (frequency, hsv_to_rgb(hue, 0.7, 0.95))
+    }).collect()
+}
+
+fn hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> [u8; 3] {
+    let c = value * saturation;
+    let x = c * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let m = value - c;
+    let (r, g, b) = match hue as u32 / 60 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    [((r + m) * 255.0) as u8, ((g + m) * 255.0) as u8, ((b + m) * 255.0) as u8]
+}
+
+const BACKGROUND: [u8; 3] = [20, 20, 30];
+// antinodes from more than one frequency
+const OVERLAP: [u8; 3] = [255, 255, 255];
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Canvas {
+        Canvas { width, height, pixels: BACKGROUND.repeat(width * height) }
+    }
+
+    fn set(&mut self, x: isize, y: isize, colour: [u8; 3]) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+        let idx = (y as usize * self.width + x as usize) * 3;
+        self.pixels[idx..idx + 3].copy_from_slice(&colour);
+    }
+
+    fn fill_rect(&mut self, x: isize, y: isize, size: isize, colour: [u8; 3]) {
+        for py in y..y + size {
+            for px in x..x + size {
+                self.set(px, py, colour);
+            }
+        }
+    }
+
+    // Square outline, used as the antinode marker
+    fn outline_rect(&mut self, x: isize, y: isize, size: isize, colour: [u8; 3]) {
+        for i in 0..size {
+            self.set(x + i, y, colour);
+            self.set(x + i, y + size - 1, colour);
+            self.set(x, y + i, colour);
+            self.set(x + size - 1, y + i, colour);
+        }
+    }
+
+    // Bresenham's line algorithm
+    fn line(&mut self, from: (isize, isize), to: (isize, isize), colour: [u8; 3]) {
+        let (mut x, mut y) = from;
+        let dx = (to.0 - x).abs();
+        let dy = -(to.1 - y).abs();
+        let sx = if x < to.0 { 1 } else { -1 };
+        let sy = if y < to.1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        loop {
+            self.set(x, y, colour);
+            if (x, y) == to {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+}
+
+// Lines run from the far antenna of each pair through the near one to the antinode
+fn pair_lines(antinodes: &[Antinode]) -> Vec<(Point, Point, char)> {
+    let mut lines: Vec<(Point, Point, char)> = antinodes.iter()
+        .filter(|an| an.b.position != an.position)
+        .map(|an| (an.b.position, an.position, an.a.frequency))
+        .collect();
+    lines.sort_by_key(|(from, to, f)| (*f, from.y, from.x, to.y, to.x));
+    lines.dedup();
+    lines
+}
+
+fn export_png(
+    path: &Path,
+    max: &Point,
+    antenna_map: &HashMap<char, Vec<Antenna>>,
+    antinodes: &[Antinode],
+    draw_lines: bool,
+    scale: usize,
+) -> io::Result<()> {
+    let colours = frequency_colours(antenna_map);
+    let mut canvas = Canvas::new((max.x + 1) * scale, (max.y + 1) * scale);
+    let cell = scale as isize;
+    let centre = |p: &Point| (p.x as isize * cell + cell / 2, p.y as isize * cell + cell / 2);
+
+    if draw_lines {
+        for (from, to, frequency) in pair_lines(antinodes) {
+            // dim the lines so markers stay visible on top
+            let colour = colours[&frequency].map(|c| c / 2);
+            canvas.line(centre(&from), centre(&to), colour);
+        }
+    }
+    for antennas in antenna_map.values() {
+        for antenna in antennas {
+            let inset = cell / 4;
+            let (x, y) = (antenna.position.x as isize * cell, antenna.position.y as isize * cell);
+            canvas.fill_rect(x + inset, y + inset, cell - 2 * inset, colours[&antenna.frequency]);
+        }
+    }
+    for (p, frequencies) in frequencies_by_cell(antinodes) {
+        let colour = if frequencies.len() > 1 { OVERLAP } else { colours[&frequencies[0]] };
+        canvas.outline_rect(p.x as isize * cell, p.y as isize * cell, cell, colour);
+    }
+
+    let file = io::BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, canvas.width as u32, canvas.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&canvas.pixels).map_err(io::Error::other)?;
+    Ok(())
+}
+
+fn hex(colour: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", colour[0], colour[1], colour[2])
+}
+
+fn export_svg(
+    path: &Path,
+    max: &Point,
+    antenna_map: &HashMap<char, Vec<Antenna>>,
+    antinodes: &[Antinode],
+    draw_lines: bool,
+) -> io::Result<()> {
+    let colours = frequency_colours(antenna_map);
+    // each cell is 10 units square, centred on (10x + 5, 10y + 5)
+    let centre = |p: &Point| (p.x * 10 + 5, p.y * 10 + 5);
+    let (width, height) = ((max.x + 1) * 10, (max.y + 1) * 10);
+
+    let mut svg = String::new();
+    svg.push_str(&format!("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {} {}\" width=\"{}\" height=\"{}\">\n", width, height, width * 2, height * 2));
+    svg.push_str(&format!("<rect width=\"{}\" height=\"{}\" fill=\"{}\"/>\n", width, height, hex(BACKGROUND)));
+    if draw_lines {
+        for (from, to, frequency) in pair_lines(antinodes) {
+            let ((x1, y1), (x2, y2)) = (centre(&from), centre(&to));
+            svg.push_str(&format!("<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-opacity=\"0.5\"/>\n", x1, y1, x2, y2, hex(colours[&frequency])));
+        }
+    }
+    let mut frequencies: Vec<&char> = antenna_map.keys().collect();
+    frequencies.sort();
+    for frequency in frequencies {
+        for antenna in &antenna_map[frequency] {
+            let (x, y) = (antenna.position.x * 10 + 2, antenna.position.y * 10 + 2);
+            svg.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"6\" height=\"6\" fill=\"{}\"><title>{} at {},{}</title></rect>\n", x, y, hex(colours[frequency]), escape_xml(&frequency.to_string()), antenna.position.x, antenna.position.y));
+        }
+    }
+    for (p, frequencies) in frequencies_by_cell(antinodes) {
+        let colour = if frequencies.len() > 1 { OVERLAP } else { colours[&frequencies[0]] };
+        let (cx, cy) = centre(&p);
+        svg.push_str(&format!("<circle cx=\"{}\" cy=\"{}\" r=\"4\" fill=\"none\" stroke=\"{}\"><title>antinode {},{}: {}</title></circle>\n", cx, cy, hex(colour), p.x, p.y, escape_xml(&frequency_list(&frequencies))));
+    }
+    svg.push_str("</svg>\n");
+
+    let mut file = File::create(path)?;
+    file.write_all(svg.as_bytes())
+}
+
+// Frequencies can be any character but '.', including ones XML gives a meaning to
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn frequency_list(frequencies: &[char]) -> String {
+    frequencies.iter().map(|f| f.to_string()).collect::<Vec<String>>().join(" ")
 }
 
 fn main() -> io::Result<()> {
+
+    // Usage: [command] [options]
+    // Commands (default just prints the answers):
+    //   grid                 draw the antennas and antinodes
+    //   frequency <f>        antinodes produced by frequency f
+    //   cell <x> <y>         antenna pairs producing an antinode at x,y
+    //   overlaps             cells where antinodes of several frequencies overlap
+    //   on-antennas          antinodes that land on an antenna
+    //   export <file>        save the map as a .png or .svg image
+    // Options:
+    //   --part <1|2>         which antinodes the commands look at (default 2)
+    //   --harmonics <range>  also count antinodes for a custom range of multiples, e.g. "1..3",
+    //                        and use those for the commands instead of --part's
+    //   --lines              when exporting, join each antenna pair to its antinodes
+    //   --scale <n>          pixels per cell for png export (default 8)
+    let args: Vec<String> = env::args().skip(1).collect();
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let custom_harmonics = match args.iter().position(|a| a == "--harmonics").and_then(|i| args.get(i + 1)) {
+        Some(range) => Some(Harmonics::parse(range).ok_or_else(|| invalid(format!("Invalid harmonic range '{}'", range)))?),
+        None => None,
+    };
+    let part = args.iter().position(|a| a == "--part").and_then(|i| args.get(i + 1)).map(|p| p.as_str());
+    let draw_lines = args.iter().any(|a| a == "--lines");
+    let scale: usize = args.iter().position(|a| a == "--scale")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|n| n.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(8);
+    // anything that isn't an option or an option's value is the command
+    let value_options = ["--part", "--harmonics", "--scale"];
+    let command: Vec<&str> = args.iter()
+        .enumerate()
+        .filter(|(i, a)| !a.starts_with("--") && (*i == 0 || !value_options.contains(&args[i - 1].as_str())))
+        .map(|(_, a)| a.as_str())
+        .collect();
+
     // Open the file
     let path = Path::new("input");
     //let path = Path::new("sample");
-    let file = File::open(&path)?;
+    let file = File::open(path)?;
     let reader = io::BufReader::new(file);
 
-    let mut antinodes: Vec<Antinode> = Vec::new();
     let mut antenna_map: HashMap<char, Vec<Antenna>> = HashMap::new();
 
     let mut antenna_locations: HashMap<Point, char> = HashMap::new();
-    let mut antinode_locations: HashMap<Point, usize> = HashMap::new();
-    let mut antinode_locations_p2: HashMap<Point, usize> = HashMap::new();
 
     let mut max_x: usize = 0;
     let mut max_y: usize = 0;
@@ -135,7 +495,7 @@ fn main() -> io::Result<()> {
                     position: Point { x: col_idx, y: row_idx },
                     frequency : ch,
                 };
-                antenna_map.entry(ch).or_insert_with(Vec::new).push(antenna);
+                antenna_map.entry(ch).or_default().push(antenna);
                 antenna_locations.insert(antenna.position, ch);
             }
         }
@@ -143,40 +503,76 @@ fn main() -> io::Result<()> {
 
     let max_pos: Point = Point { x: max_x, y: max_y };
 
-    for (_frequency, antennas) in &mut antenna_map {
-        for i in 0..antennas.len() {
-            for j in i + 1..antennas.len() {
-                let a = &antennas[i];
-                let b = &antennas[j];
-                let d = &a.distance_to(b);
-                if let Some(antinode_pos) = antinode_position(&a.position, &d, &max_pos) {
-                    antinodes.push(Antinode{ position: antinode_pos, a: a.clone(), b: b.clone() });
-                }
-                let e = b.distance_to(a);
-                if let Some(antinode_pos) = antinode_position(&b.position, &e, &max_pos) {
-                    antinodes.push(Antinode{ position: antinode_pos, a: b.clone(), b: a.clone() });
-                }
-                
-                if let Some(antinode_points) = find_antinodes_p2(&a.position, &d, &max_pos) {
-                    for point in antinode_points {
-                        *antinode_locations_p2.entry(point).or_insert(0) += 1;
-                    }
-                }
+    let antinodes = find_all_antinodes(&antenna_map, &Harmonics::PART1, &max_pos);
+    let antinodes_p2 = find_all_antinodes(&antenna_map, &Harmonics::PART2, &max_pos);
+
+    let antinode_locations_p1 = antinode_locations(&antinodes);
+    let antinode_locations_p2 = antinode_locations(&antinodes_p2);
+
+    let part1_answer = antinode_locations_p1.len();
+    let part2_answer = antinode_locations_p2.len();
+    let custom_antinodes = custom_harmonics.map(|harmonics| find_all_antinodes(&antenna_map, &harmonics, &max_pos));
+
+    let selected: &[Antinode] = match (&custom_antinodes, part) {
+        (Some(custom), None) => custom,
+        (Some(_), Some(_)) => return Err(invalid("Use either --part or --harmonics, not both".to_string())),
+        (None, Some("1")) => &antinodes,
+        (None, None | Some("2")) => &antinodes_p2,
+        (None, Some(other)) => return Err(invalid(format!("Invalid part '{}'", other))),
+    };
 
+    match command.as_slice() {
+        [] => {}
+        ["grid"] => print_grid(max_x, max_y, &antenna_locations, &antinode_locations(selected)),
+        ["frequency", f] if f.chars().count() == 1 => {
+            let frequency = f.chars().next().unwrap_or('.');
+            let cells = antinodes_for_frequency(selected, frequency);
+            println!("{} antinodes for frequency {}", cells.len(), frequency);
+            for p in cells {
+                println!("  {},{}", p.x, p.y);
             }
         }
+        ["cell", x, y] => {
+            let (Ok(x), Ok(y)) = (x.parse::<usize>(), y.parse::<usize>()) else {
+                return Err(invalid(format!("Invalid cell '{} {}'", x, y)));
+            };
+            let pairs = pairs_producing(selected, &Point { x, y });
+            println!("{} antenna pairs produce an antinode at {},{}", pairs.len(), x, y);
+            for (a, b) in pairs {
+                println!("  {} at {},{} and {},{}", a.frequency, a.position.x, a.position.y, b.position.x, b.position.y);
+            }
+        }
+        ["overlaps"] => {
+            let cells = overlapping_antinodes(selected);
+            println!("{} cells with antinodes from several frequencies", cells.len());
+            for (p, frequencies) in cells {
+                println!("  {},{}: {}", p.x, p.y, frequency_list(&frequencies));
+            }
+        }
+        ["on-antennas"] => {
+            let cells = antinodes_on_antennas(selected, &antenna_locations);
+            println!("{} antinodes land on an antenna", cells.len());
+            for (p, antenna, frequencies) in cells {
+                println!("  {},{} antenna {}: {}", p.x, p.y, antenna, frequency_list(&frequencies));
+            }
+        }
+        ["export", file] => {
+            let path = Path::new(file);
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("png") => export_png(path, &max_pos, &antenna_map, selected, draw_lines, scale)?,
+                Some("svg") => export_svg(path, &max_pos, &antenna_map, selected, draw_lines)?,
+                _ => return Err(invalid(format!("Can only export .png or .svg, not '{}'", file))),
+            }
+            println!("Wrote {}", file);
+        }
+        _ => return Err(invalid(format!("Unknown command '{}'", command.join(" ")))),
     }
 
-    for an in &antinodes {
-        *antinode_locations.entry(an.position).or_insert(0) += 1;
-    }
-
-    print_grid(max_x, max_y, &antenna_locations, &antinode_locations_p2);
-    let part1_answer = antinode_locations.len();
-    let part2_answer = antinode_locations_p2.len();
     println!("Part1: {:?}", part1_answer);
     println!("Part2: {:?}", part2_answer);
+    if let (Some(harmonics), Some(custom)) = (custom_harmonics, &custom_antinodes) {
+        println!("Harmonics {:?}..{:?}: {:?}", harmonics.min, harmonics.max, antinode_locations(custom).len());
+    }
 
     Ok(())
 }
-
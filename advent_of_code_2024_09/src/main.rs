@@ -1,7 +1,8 @@
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
-use std::collections::HashMap;
+use std::collections::BTreeSet;
+use std::env;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct DiskSpace {
@@ -11,189 +12,438 @@ struct DiskSpace {
 }
 
 
-fn find_tail_file_ds (diskmap: &Vec<DiskSpace>, start: usize, last: usize) -> (usize, Option<DiskSpace>) {
-    let mut search_idx: usize = last;
-    if start >= last || last >= diskmap.len() {
-        return (last, None);
+fn build_diskmap(digits: &[u8]) -> Vec<DiskSpace> {
+    let mut diskmap: Vec<DiskSpace> = Vec::new();
+    for (col_idx, &digit) in digits.iter().enumerate() {
+        let is_file: bool = col_idx % 2 == 0;
+        let file_id: usize = col_idx / 2;
+        let length = digit as usize;
+        // only store files or non-zero space
+        if is_file || length > 0 {
+            diskmap.push(DiskSpace {
+                id: file_id,
+                length,
+                file: is_file,
+            });
+        }
     }
-    while search_idx > start && (!diskmap[search_idx].file || diskmap[search_idx].length == 0) {
-        search_idx -= 1;
+    diskmap
+}
+
+// Part 1 without building the disk: a left cursor walks forwards through the
+// map while a right cursor hands out the blocks of the last file still to
+// move. Only the two cursors and the running position are kept, so the only
+// allocation is the digit buffer itself.
+fn stream_checksum(digits: &[u8]) -> u128 {
+    if digits.is_empty() {
+        return 0;
     }
-    let found = diskmap[search_idx];
-    if search_idx > start && found.file && found.length > 0 {
-        return (search_idx, Some(found));
+    let mut checksum: u128 = 0;
+    let mut position: u128 = 0;
+    let mut left: usize = 0;
+    // last file, skipping any trailing free space
+    let mut right: usize = (digits.len() - 1) & !1;
+    let mut right_remaining: u128 = digits[right] as u128;
+
+    while left <= right {
+        if left.is_multiple_of(2) {
+            // a file stays where it is, unless the right cursor has already taken some of it
+            let length = if left == right { right_remaining } else { digits[left] as u128 };
+            checksum += blocks_checksum(left / 2, position, length);
+            position += length;
+        }
+        else {
+            let mut space = digits[left] as u128;
+            while space > 0 && right > left {
+                let take = space.min(right_remaining);
+                checksum += blocks_checksum(right / 2, position, take);
+                position += take;
+                space -= take;
+                right_remaining -= take;
+                if right_remaining == 0 {
+                    if right < 2 {
+                        break;
+                    }
+                    right -= 2;
+                    right_remaining = digits[right] as u128;
+                }
+            }
+        }
+        left += 1;
     }
-    return (search_idx, None);
+    checksum
+}
+
+// id * (position + ... + position + length - 1)
+fn blocks_checksum(id: usize, position: u128, length: u128) -> u128 {
+    id as u128 * (position * length + length * length.saturating_sub(1) / 2)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FileSpan {
+    id: usize,
+    start: usize,
+    length: usize,
+}
+
+// Free spans bucketed by length, each bucket an ordered set of start positions.
+// The leftmost gap of a given length is the first entry of its bucket, so a
+// lookup only checks one bucket per length.
+struct FreeSpaces {
+    by_length: Vec<BTreeSet<usize>>,
 }
 
-fn find_first_space_idx (diskmap: &Vec<DiskSpace>, size: usize, limit: usize) -> Option<usize> {
-    for (idx, ds) in diskmap.iter().enumerate() {
-        if !ds.file && ds.length >= size && idx < limit {
-            return Some(idx);
+impl FreeSpaces {
+    fn new(spans: &[(usize, usize)]) -> FreeSpaces {
+        let longest = spans.iter().map(|&(_, length)| length).max().unwrap_or(0);
+        let mut free = FreeSpaces { by_length: vec![BTreeSet::new(); longest + 1] };
+        for &(start, length) in spans {
+            free.insert(start, length);
         }
+        free
     }
-    None
-}
 
-fn print_diskmap (diskmap: &Vec<DiskSpace>) {
+    fn insert(&mut self, start: usize, length: usize) {
+        if length > 0 {
+            self.by_length[length].insert(start);
+        }
+    }
 
-    for ds in diskmap {
-        for _ in 0..ds.length {
-            if ds.file {
-                print!("{}", ds.id);
-            }
-            else {
-                print!(".");
-            }
+    // The leftmost span of each length that holds length blocks and starts
+    // before limit, as (start, span length)
+    fn candidates(&self, length: usize, limit: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (length.max(1)..self.by_length.len())
+            .filter_map(move |l| self.by_length[l].first().filter(|&&start| start < limit).map(|&start| (start, l)))
+    }
+
+    // The first span at or after from that holds length blocks and starts before limit
+    fn first_from(&self, length: usize, from: usize, limit: usize) -> Option<(usize, usize)> {
+        if from >= limit {
+            return None;
         }
-    }        
-    println!("");
+        (length.max(1)..self.by_length.len())
+            .filter_map(|l| self.by_length[l].range(from..limit).next().map(|&start| (start, l)))
+            .min()
+    }
 
+    // Put length blocks at the start of a span. Whatever is left of the span
+    // goes back in the bucket for its new length.
+    fn take(&mut self, start: usize, span_length: usize, length: usize) {
+        self.by_length[span_length].remove(&start);
+        self.insert(start + length, span_length - length);
+    }
+}
+
+// How to pick the free span a file moves into. Only spans that start before
+// limit (the file's current position) are offered, so files only move left.
+trait CompactionStrategy {
+    fn name(&self) -> &'static str;
 
+    // (start, span length) of the chosen span
+    fn choose(&mut self, free: &FreeSpaces, length: usize, limit: usize) -> Option<(usize, usize)>;
 }
 
-fn find_position<T: PartialEq>(vec: &Vec<T>, value: T) -> Option<usize> {
-    vec.iter().position(|x| *x == value)
+// Leftmost span that fits, as in part 2
+struct FirstFit;
+// Tightest fitting span, leftmost on a tie
+struct BestFit;
+// Largest span, leftmost on a tie
+struct WorstFit;
+// First fit, but carrying on from where the last file was put and wrapping
+// back to the start of the disk
+struct NextFit {
+    rover: usize,
 }
 
+impl CompactionStrategy for FirstFit {
+    fn name(&self) -> &'static str {
+        "first-fit"
+    }
 
-fn main() -> io::Result<()> {
+    fn choose(&mut self, free: &FreeSpaces, length: usize, limit: usize) -> Option<(usize, usize)> {
+        free.candidates(length, limit).min()
+    }
+}
 
+impl CompactionStrategy for BestFit {
+    fn name(&self) -> &'static str {
+        "best-fit"
+    }
 
-    // Open the file
-    let path = Path::new("input");
-    //let path = Path::new("sample");
-    //let path = Path::new("sample2");
-    let file = File::open(&path)?;
-    let reader = io::BufReader::new(file);
+    fn choose(&mut self, free: &FreeSpaces, length: usize, limit: usize) -> Option<(usize, usize)> {
+        free.candidates(length, limit).next()
+    }
+}
 
-    let mut part1_answer: usize = 0;
-    let mut part2_answer: usize = 0;
+impl CompactionStrategy for WorstFit {
+    fn name(&self) -> &'static str {
+        "worst-fit"
+    }
 
+    fn choose(&mut self, free: &FreeSpaces, length: usize, limit: usize) -> Option<(usize, usize)> {
+        free.candidates(length, limit).last()
+    }
+}
 
-    let mut diskmap: Vec<DiskSpace> = Vec::new();
+impl CompactionStrategy for NextFit {
+    fn name(&self) -> &'static str {
+        "next-fit"
+    }
 
-    // Process each line
-    for line in reader.lines() {
-        let line = line?;
-        for (col_idx, ch) in line.chars().enumerate() {
-            let is_file: bool = col_idx % 2 == 0;
-            let file_id: usize = col_idx / 2;
-            let length = ch.to_digit(10).unwrap_or(0) as usize;
-            // only store files or non-zero space
-            if is_file || length > 0 {
-                diskmap.push(DiskSpace {
-                    id: file_id,
-                    length: length,
-                    file: is_file,
-                });
-            }
+    fn choose(&mut self, free: &FreeSpaces, length: usize, limit: usize) -> Option<(usize, usize)> {
+        let chosen = free.first_from(length, self.rover, limit)
+            .or_else(|| free.first_from(length, 0, self.rover.min(limit)));
+        if let Some((start, _)) = chosen {
+            self.rover = start + length;
         }
+        chosen
     }
+}
 
+fn strategy_by_name(name: &str) -> Option<Box<dyn CompactionStrategy>> {
+    match name {
+        "first-fit" => Some(Box::new(FirstFit)),
+        "best-fit" => Some(Box::new(BestFit)),
+        "worst-fit" => Some(Box::new(WorstFit)),
+        "next-fit" => Some(Box::new(NextFit { rover: 0 })),
+        _ => None,
+    }
+}
 
+const STRATEGIES: [&str; 4] = ["first-fit", "best-fit", "worst-fit", "next-fit"];
 
-    let mut last_tail_file_idx = diskmap.len() - 1;
-    let mut contiguous_diskmap: Vec<DiskSpace> = Vec::new();
+#[derive(Debug, Clone)]
+struct Compaction {
+    files: Vec<FileSpan>,
+    moves: Vec<Move>,
+}
 
-    let mut remaining_diskspace: HashMap<DiskSpace,usize> = HashMap::new();
-    let mut moved_diskspace: HashMap<DiskSpace,usize> = HashMap::new();
+// A file moving from the span starting at from to the span starting at to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Move {
+    id: usize,
+    from: usize,
+    to: usize,
+    length: usize,
+}
 
-    for (idx, ds) in diskmap.iter().enumerate() {
-        if last_tail_file_idx <= idx {
-            break;
-        }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CompactionReport {
+    checksum: usize,
+    free_spans: usize,
+    largest_free_span: usize,
+    files_moved: usize,
+    blocks_moved: usize,
+}
+
+// Lay the disk map out as file spans and free spans with their block positions
+fn layout(diskmap: &[DiskSpace]) -> (Vec<FileSpan>, Vec<(usize, usize)>) {
+    let mut files: Vec<FileSpan> = Vec::new();
+    let mut spaces: Vec<(usize, usize)> = Vec::new();
+    let mut position: usize = 0;
+    for ds in diskmap {
         if ds.file {
-            //println!("inserting file block {:?}", ds);
-            contiguous_diskmap.push(ds.clone());
+            files.push(FileSpan { id: ds.id, start: position, length: ds.length });
         }
         else {
-            let mut space = ds.length;
-            while space > 0 {
-                if let (tail_idx, Some(tail_ds)) = find_tail_file_ds(&diskmap, idx, last_tail_file_idx) {
-                    last_tail_file_idx = tail_idx;
-                    let mut tail_length = *remaining_diskspace.entry(tail_ds).or_insert(tail_ds.length);
-                    let new_ds = DiskSpace{
-                        id: tail_ds.id,
-                        length: if space < tail_length { space } else { tail_length },
-                        file: true,
-                    };
-                    //println!("creating new file block {:?} from {:?}", new_ds, tail_ds);
-                    contiguous_diskmap.push(new_ds);
-                    if space >= tail_length {
-                        last_tail_file_idx -= 1;
-                    }
-                    tail_length -= new_ds.length;
-                    if tail_length > 0 {
-                        *remaining_diskspace.entry(tail_ds).or_insert(0) = tail_length;
-                    }
-                    else {
-                        remaining_diskspace.remove(&tail_ds);
-                    }
-                    //tail_ds.length -= new_ds.length;
-                    space -= new_ds.length;
-                    //println!("tail file block now {:?} new length {}", tail_ds, tail_length);
-                    //println!("remaining space to fill: {}", space);
-                }
-                else {
-                    break;
-                }
-            }
+            spaces.push((position, ds.length));
         }
+        position += ds.length;
     }
-    //println!("remaining {:?}", remaining_diskspace);
-    for (ds, remaining_length) in remaining_diskspace {
-        if remaining_length > 0 {
-            contiguous_diskmap.push(DiskSpace { id: ds.id, length: remaining_length, file: true } );
+    (files, spaces)
+}
+
+// Move whole files, highest id first, into the span the strategy picks
+fn compact_files(diskmap: &[DiskSpace], strategy: &mut dyn CompactionStrategy) -> Compaction {
+    let (mut files, spaces) = layout(diskmap);
+    let mut free = FreeSpaces::new(&spaces);
+    let mut moves: Vec<Move> = Vec::new();
+    for file in files.iter_mut().rev() {
+        if file.length == 0 {
+            continue;
+        }
+        if let Some((start, span_length)) = strategy.choose(&free, file.length, file.start) {
+            free.take(start, span_length, file.length);
+            moves.push(Move { id: file.id, from: file.start, to: start, length: file.length });
+            file.start = start;
         }
     }
+    Compaction { files, moves }
+}
 
-
-    let mut block_counter: usize = 0;
-    for ds in contiguous_diskmap {
-        for _ in 0..ds.length {
-            part1_answer += block_counter * ds.id;
-            block_counter += 1;
-            //print!("{}", ds.id);
+// Apply the moves one at a time to the original layout, calling step with
+// the move and the files as they are after it
+fn replay(diskmap: &[DiskSpace], moves: &[Move], mut step: impl FnMut(&Move, &[FileSpan])) -> Result<Vec<FileSpan>, String> {
+    let (mut files, _) = layout(diskmap);
+    // file ids are their index in the layout
+    for m in moves {
+        let file = files.get_mut(m.id).ok_or_else(|| format!("move of file {}, which isn't on the disk", m.id))?;
+        if (file.start, file.length) != (m.from, m.length) {
+            return Err(format!("move of file {} from {} ({} blocks) doesn't match the disk, where it's at {} ({} blocks)",
+                m.id, m.from, m.length, file.start, file.length));
         }
+        file.start = m.to;
+        step(m, &files);
     }
+    Ok(files)
+}
 
-    let mut defragged = diskmap.to_vec();
-    let mut insert_count: usize = 0;
-    //print_diskmap(&defragged);
-    for idx in (0..diskmap.len()).rev() {
-        let ds = diskmap[idx];
-        if !ds.file || moved_diskspace.contains_key(&ds) {
-            continue;
+// One row per width blocks, labelled with the block range, each block's file
+// id in a fixed width column so large ids can't run together. '.' is free space.
+fn render_disk(files: &[FileSpan], disk_length: usize, width: usize) -> String {
+    let mut blocks: Vec<Option<usize>> = vec![None; disk_length];
+    for f in files {
+        for block in &mut blocks[f.start..f.start + f.length] {
+            *block = Some(f.id);
         }
-        let original_pos = find_position(&defragged, ds).unwrap_or(idx + insert_count);
-        if let Some(new_idx) = find_first_space_idx(&defragged, ds.length, original_pos) {
-            let target = defragged[new_idx];
-            //println!("Moving {:?} to position {} from {}", ds, new_idx, original_pos);
-            let difference = target.length - ds.length;
-
-            defragged.swap(new_idx, original_pos);
-            if difference > 0 {
-                defragged[original_pos].length = ds.length;
-                defragged.insert(new_idx + 1, DiskSpace{ id: 0, length: target.length - ds.length, file: false});
-                insert_count += 1;
+    }
+    let column = files.iter().map(|f| f.id).max().unwrap_or(0).to_string().len();
+    let label = disk_length.saturating_sub(1).to_string().len();
+
+    let mut output = String::new();
+    for (row, chunk) in blocks.chunks(width.max(1)).enumerate() {
+        let first = row * width.max(1);
+        output.push_str(&format!("{:>label$}..{:>label$} |", first, first + chunk.len() - 1, label = label));
+        for block in chunk {
+            match block {
+                Some(id) => output.push_str(&format!(" {:>column$}", id, column = column)),
+                None => output.push_str(&format!(" {:>column$}", ".", column = column)),
             }
-            //print_diskmap(&defragged);
-            moved_diskspace.insert(ds, 1);
         }
+        output.push('\n');
+    }
+    output
+}
+
+// Free spans left between (and after) the files once they have moved.
+// Space a file moved out of merges with any free space next to it.
+fn free_spans_after(files: &[FileSpan], disk_length: usize) -> Vec<(usize, usize)> {
+    let mut occupied: Vec<&FileSpan> = files.iter().filter(|f| f.length > 0).collect();
+    occupied.sort_by_key(|f| f.start);
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    let mut position: usize = 0;
+    for f in occupied {
+        if f.start > position {
+            spans.push((position, f.start - position));
+        }
+        position = f.start + f.length;
+    }
+    if disk_length > position {
+        spans.push((position, disk_length - position));
+    }
+    spans
+}
+
+fn compaction_report(diskmap: &[DiskSpace], strategy: &mut dyn CompactionStrategy) -> CompactionReport {
+    let disk_length: usize = diskmap.iter().map(|ds| ds.length).sum();
+    let compaction = compact_files(diskmap, strategy);
+    let free = free_spans_after(&compaction.files, disk_length);
+    CompactionReport {
+        checksum: compaction.files.iter().map(span_checksum).sum(),
+        free_spans: free.len(),
+        largest_free_span: free.iter().map(|&(_, length)| length).max().unwrap_or(0),
+        files_moved: compaction.moves.len(),
+        blocks_moved: compaction.moves.iter().map(|m| m.length).sum(),
+    }
+}
+
+// Sum of position * id over the span's blocks, i.e. id * (start + ... + start + length - 1)
+fn span_checksum(file: &FileSpan) -> usize {
+    file.id * (file.start * file.length + file.length * file.length.saturating_sub(1) / 2)
+}
+
+fn main() -> io::Result<()> {
+
+    // Options:
+    //   --strategy <name>  report checksum and fragmentation for one of
+    //                      first-fit, best-fit, worst-fit or next-fit
+    //   --compare          report every strategy side by side
+    //   --trace            list every move made compacting whole files and replay
+    //                      them (with --strategy if given, otherwise first-fit)
+    //   --render           draw the disk before and after compacting, and after
+    //                      each move when tracing
+    //   --width <n>        blocks per row when drawing the disk (default 20)
+    //   --stream           only work out part 1, streaming over the digits so
+    //                      huge disk maps need no more memory than the input
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut strategies: Vec<&str> = Vec::new();
+    if let Some(name) = args.iter().position(|a| a == "--strategy").and_then(|i| args.get(i + 1)) {
+        if strategy_by_name(name).is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown strategy '{}'", name)));
+        }
+        strategies.push(name);
     }
+    if args.iter().any(|a| a == "--compare") {
+        strategies = STRATEGIES.to_vec();
+    }
+    let trace = args.iter().any(|a| a == "--trace");
+    let stream_only = args.iter().any(|a| a == "--stream");
+    let render = args.iter().any(|a| a == "--render");
+    let width: usize = args.iter().position(|a| a == "--width")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(20);
 
+    // Open the file
+    let path = Path::new("input");
+    //let path = Path::new("sample");
+    //let path = Path::new("sample2");
+    let file = File::open(path)?;
+    let reader = io::BufReader::new(file);
 
-    //print_diskmap(&defragged);
-    let mut block_counter: usize = 0;
-    for ds in defragged {
-        for _ in 0..ds.length {
-            if ds.file {
-                part2_answer += block_counter * ds.id;
+    // Each byte of the disk map as its digit value
+    let mut digits: Vec<u8> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        digits.extend(line.bytes().map(|b| if b.is_ascii_digit() { b - b'0' } else { 0 }));
+    }
+
+    let part1_answer: u128 = stream_checksum(&digits);
+    if stream_only {
+        println!("Part1: {:?}", part1_answer);
+        return Ok(());
+    }
+
+    let diskmap = build_diskmap(&digits);
+
+    let part2_answer: usize = compact_files(&diskmap, &mut FirstFit).files.iter().map(span_checksum).sum();
+
+    if trace || render {
+        let disk_length: usize = diskmap.iter().map(|ds| ds.length).sum();
+        let mut strategy = strategies.first().and_then(|name| strategy_by_name(name)).unwrap_or(Box::new(FirstFit));
+        let compaction = compact_files(&diskmap, strategy.as_mut());
+        if render {
+            println!("Disk before compacting");
+            print!("{}", render_disk(&layout(&diskmap).0, disk_length, width));
+        }
+        if trace {
+            println!("{} moves using {}", compaction.moves.len(), strategy.name());
+            let replayed = replay(&diskmap, &compaction.moves, |m, files| {
+                println!("Move file {} ({} blocks) from {}..{} to {}..{}", m.id, m.length, m.from, m.from + m.length - 1, m.to, m.to + m.length - 1);
+                if render {
+                    print!("{}", render_disk(files, disk_length, width));
+                }
+            }).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            if replayed != compaction.files {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "replay didn't reach the compacted disk"));
             }
-            block_counter += 1;
         }
-    }        
+        if render {
+            println!("Disk after compacting");
+            print!("{}", render_disk(&compaction.files, disk_length, width));
+        }
+    }
+
+    if !strategies.is_empty() {
+        println!("{:<10} {:>16} {:>10} {:>12} {:>11} {:>12}", "strategy", "checksum", "free spans", "largest free", "files moved", "blocks moved");
+        for name in &strategies {
+            let Some(mut strategy) = strategy_by_name(name) else {
+                continue;
+            };
+            let report = compaction_report(&diskmap, strategy.as_mut());
+            println!("{:<10} {:>16} {:>10} {:>12} {:>11} {:>12}", strategy.name(), report.checksum, report.free_spans, report.largest_free_span, report.files_moved, report.blocks_moved);
+        }
+    }
 
     println!("Part1: {:?}", part1_answer);
     println!("Part2: {:?}", part2_answer);
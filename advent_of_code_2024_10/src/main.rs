@@ -1,100 +1,326 @@
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
+use std::env;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-// Define directions (N, S, E, W)
-const DIRECTIONS: [(isize, isize); 4] = [(0, -1), (0, 1), (-1, 0), (1, 0)];
+// Define directions (N, S, E, W), then the diagonals for 8-neighbour movement
+const DIRECTIONS: [(isize, isize); 8] = [(0, -1), (0, 1), (-1, 0), (1, 0), (-1, -1), (1, -1), (-1, 1), (1, 1)];
 
-fn main() -> io::Result<()> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Point {
+    x: usize,
+    y: usize,
+}
 
+// What counts as a hiking trail. The puzzle's rule is 0 up to 9 in steps of
+// exactly +1 moving in four directions. Steps must all head from the start
+// height towards the target so no trail can loop back on itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TrailRule {
+    start: u32,
+    target: u32,
+    steps: Vec<i64>,
+    // 4 or 8
+    neighbours: usize,
+}
 
-    // Open the file
-    let path = Path::new("input");
-    //let path = Path::new("sample");
-    //let path = Path::new("sample2");
-    //let path = Path::new("sample3");
-    //let path = Path::new("sample4");
-    //let path = Path::new("sample5");
-    let file = File::open(&path)?;
-    let reader = io::BufReader::new(file);
-    let mut part1_answer: usize = 0;
+impl TrailRule {
+    fn puzzle() -> TrailRule {
+        TrailRule { start: 0, target: 9, steps: vec![1], neighbours: 4 }
+    }
 
-    let mut part2_answer: usize = 0;
+    fn validate(&self) -> Result<(), String> {
+        if self.neighbours != 4 && self.neighbours != 8 {
+            return Err(format!("Neighbours must be 4 or 8, not {}", self.neighbours));
+        }
+        // every start cell is its own summit, so the steps don't matter
+        if self.start == self.target {
+            return Ok(());
+        }
+        let direction = (self.target as i64 - self.start as i64).signum();
+        if let Some(step) = self.steps.iter().find(|&&step| step == 0 || step.signum() != direction) {
+            return Err(format!("Step {:+} doesn't lead from {} to {}", step, self.start, self.target));
+        }
+        Ok(())
+    }
 
+    // How many height units a cell is from the target, if it could be on a trail
+    fn distance_to_target(&self, height: u32) -> Option<u32> {
+        let (low, high) = (self.start.min(self.target), self.start.max(self.target));
+        if height < low || height > high {
+            return None;
+        }
+        Some(height.abs_diff(self.target))
+    }
 
-    let grid: Vec<Vec<usize>> = reader.lines().map(|line| {
-        line.expect("Could not read line")
-            .chars()
-            .map(|ch| {
-                if ch == '.' {
-                    return 1000 as usize;
-                }
-                ch.to_digit(10).expect("Not a digit") as usize
-            })
-            .collect()
-    }).collect();
+    fn allows(&self, from: u32, to: u32) -> bool {
+        self.steps.contains(&(to as i64 - from as i64))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Trailhead {
+    position: Point,
+    // number of summits reachable
+    score: usize,
+    // number of distinct trails to any summit
+    rating: u64,
+    summits: Vec<Point>,
+}
+
+// One bit per summit, indexed in reading order
+type SummitSet = Vec<u64>;
+
+fn neighbours<'a>(grid: &'a [Vec<Option<u32>>], p: Point, rule: &TrailRule) -> impl Iterator<Item = Point> + 'a {
+    DIRECTIONS[..rule.neighbours].iter().filter_map(move |(dx, dy)| {
+        let x = p.x.checked_add_signed(*dx)?;
+        let y = p.y.checked_add_signed(*dy)?;
+        if y < grid.len() && x < grid[y].len() { Some(Point { x, y }) } else { None }
+    })
+}
+
+// Work back from the summits (cells at the target height) one height at a
+// time. Each cell's reachable summits are the union of the sets of the
+// neighbours it can step to, and its number of trails is the sum of theirs,
+// so every cell is visited once. Sets are dropped once no height still to
+// be worked out can step to them.
+fn analyse_trails(grid: &[Vec<Option<u32>>], rule: &TrailRule) -> Vec<Trailhead> {
+    let width = grid.iter().map(|row| row.len()).max().unwrap_or(0);
+    let idx = |p: Point| p.y * width + p.x;
 
+    let mut by_distance: BTreeMap<u32, Vec<Point>> = BTreeMap::new();
     for (row_idx, row) in grid.iter().enumerate() {
-        for (col_idx, col) in row.iter().enumerate() {
-            if *col == 0 {
-                let visited1 = &mut vec![vec![false; grid[0].len()]; grid.len()];
-                part1_answer += dfs(&grid, col_idx as isize, row_idx as isize, *col as isize - 1, visited1, false);
-                let visited2 = &mut vec![vec![false; grid[0].len()]; grid.len()];
-                part2_answer += dfs(&grid, col_idx as isize, row_idx as isize, *col as isize - 1, visited2, true);
+        for (col_idx, height) in row.iter().enumerate() {
+            if let Some(distance) = height.and_then(|h| rule.distance_to_target(h)) {
+                by_distance.entry(distance).or_default().push(Point { x: col_idx, y: row_idx });
             }
         }
     }
+    let summits: Vec<Point> = by_distance.get(&0).cloned().unwrap_or_default();
+    let words = summits.len().div_ceil(64);
+    let longest_step = rule.steps.iter().map(|step| step.unsigned_abs() as u32).max().unwrap_or(0);
 
+    let mut trails: Vec<u64> = vec![0; width * grid.len()];
+    let mut reachable: Vec<Option<SummitSet>> = vec![None; width * grid.len()];
+    for (bit, &summit) in summits.iter().enumerate() {
+        let mut set: SummitSet = vec![0; words];
+        set[bit / 64] |= 1 << (bit % 64);
+        reachable[idx(summit)] = Some(set);
+        trails[idx(summit)] = 1;
+    }
 
-    println!("Part1: {:?}", part1_answer);
-    println!("Part2: {:?}", part2_answer);
+    // distances whose sets are still held, nearest the target first
+    let mut unfreed = by_distance.iter().peekable();
+    for (&distance, cells) in by_distance.range(1..) {
+        for &p in cells {
+            let height = grid[p.y][p.x].unwrap_or_default();
+            let mut set: SummitSet = vec![0; words];
+            let mut count: u64 = 0;
+            for n in neighbours(grid, p, rule) {
+                if !grid[n.y][n.x].is_some_and(|next| rule.allows(height, next)) {
+                    continue;
+                }
+                if let Some(ahead) = &reachable[idx(n)] {
+                    for (word, bits) in set.iter_mut().zip(ahead) {
+                        *word |= bits;
+                    }
+                }
+                count = count.saturating_add(trails[idx(n)]);
+            }
+            reachable[idx(p)] = Some(set);
+            trails[idx(p)] = count;
+        }
+        // nothing further from the target can step back this far
+        while let Some((_, done)) = unfreed.next_if(|(&d, _)| d + longest_step <= distance) {
+            for &p in done {
+                reachable[idx(p)] = None;
+            }
+        }
+    }
 
-    Ok(())
+    let trailhead_distance = rule.start.abs_diff(rule.target);
+    let trailheads = by_distance.get(&trailhead_distance).cloned().unwrap_or_default();
+    trailheads.iter().map(|&p| {
+        let set = reachable[idx(p)].take().unwrap_or_default();
+        let reached: Vec<Point> = summits.iter()
+            .enumerate()
+            .filter(|(bit, _)| set[bit / 64] & (1 << (bit % 64)) != 0)
+            .map(|(_, &summit)| summit)
+            .collect();
+        Trailhead { position: p, score: reached.len(), rating: trails[idx(p)], summits: reached }
+    }).collect()
+}
+
+// Cells a trail can step to next from p
+fn next_steps<'a>(grid: &'a [Vec<Option<u32>>], p: Point, rule: &'a TrailRule) -> impl Iterator<Item = Point> + 'a {
+    let height = grid[p.y][p.x];
+    neighbours(grid, p, rule).filter(move |n| match (height, grid[n.y][n.x]) {
+        (Some(from), Some(to)) => rule.allows(from, to),
+        _ => false,
+    })
 }
 
-// Depth-First Search Function
-fn dfs(
-    grid: &Vec<Vec<usize>>,
-    x: isize,
-    y: isize,
-    current_value: isize,
-    visited: &mut Vec<Vec<bool>>,
-    p2: bool
-) -> usize {
-    // Check bounds
-    if y < 0 || y >= grid.len() as isize || x < 0 || x >= grid[0].len() as isize {
-        return 0;
+// Depth first list of the distinct trails from start, stopping once limit are found
+fn list_trails(grid: &[Vec<Option<u32>>], rule: &TrailRule, start: Point, limit: usize) -> Vec<Vec<Point>> {
+    fn walk(grid: &[Vec<Option<u32>>], rule: &TrailRule, trail: &mut Vec<Point>, limit: usize, found: &mut Vec<Vec<Point>>) {
+        let Some(&p) = trail.last() else {
+            return;
+        };
+        if found.len() >= limit {
+            return;
+        }
+        if grid[p.y][p.x] == Some(rule.target) {
+            found.push(trail.clone());
+            return;
+        }
+        for n in next_steps(grid, p, rule) {
+            trail.push(n);
+            walk(grid, rule, trail, limit, found);
+            trail.pop();
+        }
+    }
+
+    let mut found: Vec<Vec<Point>> = Vec::new();
+    if grid[start.y][start.x] == Some(rule.start) {
+        walk(grid, rule, &mut vec![start], limit, &mut found);
     }
+    found
+}
 
-    // Check if already visited or if value isn't one increment higher
-    let value = grid[y as usize][x as usize] as isize;
-    if visited[y as usize][x as usize] || value != current_value + 1 {
-        return 0;
+// Every cell on at least one trail from start, without listing the trails
+fn trail_cells(grid: &[Vec<Option<u32>>], rule: &TrailRule, start: Point) -> HashSet<Point> {
+    fn on_trail(grid: &[Vec<Option<u32>>], rule: &TrailRule, p: Point, memo: &mut HashMap<Point, bool>) -> bool {
+        if let Some(&known) = memo.get(&p) {
+            return known;
+        }
+        let mut result = grid[p.y][p.x] == Some(rule.target);
+        if !result {
+            // visit every step so all the cells on trails get marked
+            for n in next_steps(grid, p, rule).collect::<Vec<Point>>() {
+                result |= on_trail(grid, rule, n, memo);
+            }
+        }
+        memo.insert(p, result);
+        result
     }
 
-    // Mark as visited
-    visited[y as usize][x as usize] = true;
+    let mut memo: HashMap<Point, bool> = HashMap::new();
+    if grid[start.y][start.x] == Some(rule.start) {
+        on_trail(grid, rule, start, &mut memo);
+    }
+    memo.into_iter().filter(|&(_, on)| on).map(|(p, _)| p).collect()
+}
 
-    // If we reached 9, we found a valid path
-    if value == 9 {
-        // part 1 we want to remember that we have seen this 9 as we count number
-        // of 9's that can be reached.
-        // part 2 we want to find as many ways to reach all 9's so we forget it
-        // to allow us to find another way to get here
-        if p2 {
-            visited[y as usize][x as usize] = false;
+// The map with only the given cells' heights shown, like the puzzle examples
+fn render_trail(grid: &[Vec<Option<u32>>], cells: &HashSet<Point>) -> String {
+    let mut output = String::new();
+    for (row_idx, row) in grid.iter().enumerate() {
+        for (col_idx, height) in row.iter().enumerate() {
+            match height {
+                Some(h) if cells.contains(&Point { x: col_idx, y: row_idx }) => output.push_str(&h.to_string()),
+                _ => output.push('.'),
+            }
         }
-        return 1;
+        output.push('\n');
     }
+    output
+}
 
-    // Explore all four directions
-    let mut paths = 0;
-    for (dx, dy) in DIRECTIONS.iter() {
-        paths += dfs(grid, x + dx, y + dy, value as isize, visited, p2);
+fn main() -> io::Result<()> {
+
+    // Options:
+    //   --details         print the score, rating and reachable summits of each trailhead
+    //   --start <h>       height trails start at (default 0)
+    //   --target <h>      height trails finish at (default 9)
+    //   --steps <list>    allowed height changes per step, e.g. "+1,+2" or "-1" (default +1)
+    //   --neighbours <n>  4 or 8 way movement (default 4)
+    //   --trails <x,y>    list the distinct trails from the trailhead at x,y
+    //   --limit <n>       list at most n trails (default 10)
+    //   --render          draw each listed trail on the map
+    //   --union           draw every cell on any trail from the trailhead
+    let args: Vec<String> = env::args().skip(1).collect();
+    let details = args.iter().any(|a| a == "--details");
+    let option = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let mut rule = TrailRule::puzzle();
+    if let Some(start) = option("--start") {
+        rule.start = start.parse().map_err(|_| invalid(format!("Invalid start height '{}'", start)))?;
+    }
+    if let Some(target) = option("--target") {
+        rule.target = target.parse().map_err(|_| invalid(format!("Invalid target height '{}'", target)))?;
     }
+    if let Some(steps) = option("--steps") {
+        rule.steps = steps.split(',')
+            .map(|step| step.trim().parse::<i64>())
+            .collect::<Result<Vec<i64>, _>>()
+            .map_err(|_| invalid(format!("Invalid steps '{}'", steps)))?;
+    }
+    if let Some(neighbours) = option("--neighbours") {
+        rule.neighbours = neighbours.parse().map_err(|_| invalid(format!("Invalid neighbours '{}'", neighbours)))?;
+    }
+    rule.validate().map_err(invalid)?;
+    let trails_from = match option("--trails") {
+        Some(xy) => {
+            let parsed = xy.split_once(',').and_then(|(x, y)| Some(Point { x: x.trim().parse().ok()?, y: y.trim().parse().ok()? }));
+            Some(parsed.ok_or_else(|| invalid(format!("Invalid trailhead '{}'", xy)))?)
+        }
+        None => None,
+    };
+    let limit: usize = option("--limit").and_then(|n| n.parse().ok()).unwrap_or(10);
+    let render = args.iter().any(|a| a == "--render");
+    let union = args.iter().any(|a| a == "--union");
 
-    // Backtrack
-    visited[y as usize][x as usize] = false;
+    // Open the file
+    let path = Path::new("input");
+    //let path = Path::new("sample");
+    //let path = Path::new("sample2");
+    //let path = Path::new("sample3");
+    //let path = Path::new("sample4");
+    //let path = Path::new("sample5");
+    let file = File::open(path)?;
+    let reader = io::BufReader::new(file);
+
+    // '.' (or anything that isn't a height) is impassable
+    let grid: Vec<Vec<Option<u32>>> = reader.lines().map(|line| {
+        line.expect("Could not read line")
+            .chars()
+            .map(|ch| ch.to_digit(10))
+            .collect()
+    }).collect();
+
+    let trailheads = analyse_trails(&grid, &rule);
+    if details {
+        for t in &trailheads {
+            let summits: Vec<String> = t.summits.iter().map(|s| format!("{},{}", s.x, s.y)).collect();
+            println!("Trailhead {},{}: score {} rating {} summits {}", t.position.x, t.position.y, t.score, t.rating, summits.join(" "));
+        }
+    }
 
-    paths
+    if let Some(start) = trails_from {
+        if grid.get(start.y).and_then(|row| row.get(start.x)).copied().flatten() != Some(rule.start) {
+            return Err(invalid(format!("{},{} isn't a trailhead", start.x, start.y)));
+        }
+        let trails = list_trails(&grid, &rule, start, limit);
+        println!("{} trails listed from {},{}", trails.len(), start.x, start.y);
+        for (n, trail) in trails.iter().enumerate() {
+            let steps: Vec<String> = trail.iter().map(|p| format!("{},{}", p.x, p.y)).collect();
+            println!("Trail {}: {}", n + 1, steps.join(" -> "));
+            if render {
+                print!("{}", render_trail(&grid, &trail.iter().copied().collect()));
+            }
+        }
+        if union {
+            println!("All trails from {},{}", start.x, start.y);
+            print!("{}", render_trail(&grid, &trail_cells(&grid, &rule, start)));
+        }
+    }
+
+    let part1_answer: usize = trailheads.iter().map(|t| t.score).sum();
+    let part2_answer: u64 = trailheads.iter().map(|t| t.rating).sum();
+
+    println!("Part1: {:?}", part1_answer);
+    println!("Part2: {:?}", part2_answer);
+
+    Ok(())
 }
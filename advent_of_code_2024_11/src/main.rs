@@ -1,95 +1,523 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, BufRead};
 use std::path::Path;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::env;
 //use regex::Regex;
 
-fn blink (stones: Vec<usize>) -> Vec<usize> {
-    let mut new_stones: Vec<usize> = Vec::new();
+// Number of stones, or None once it no longer fits in a u128
+type Count = Option<u128>;
 
-    for s in stones {
-        if s == 0 {
-            new_stones.push(1);
-            continue;
+#[derive(Debug, Clone, Copy)]
+enum Predicate {
+    // the stone has exactly this value
+    Value(u128),
+    // the stone's digit count is a multiple of this
+    DigitsMultipleOf(u32),
+    Always,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Transform {
+    // replace the stone with one of this value
+    Value(u128),
+    // cut the digits into this many equal parts
+    Split(u32),
+    Multiply(u128),
+    Add(u128),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rule {
+    predicate: Predicate,
+    transform: Transform,
+}
+
+#[derive(Debug, Clone)]
+struct RuleSet {
+    // base used for counting and splitting digits
+    base: u128,
+    // the first rule whose predicate matches is applied
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    fn puzzle() -> RuleSet {
+        RuleSet {
+            base: 10,
+            rules: vec![
+                Rule { predicate: Predicate::Value(0), transform: Transform::Value(1) },
+                Rule { predicate: Predicate::DigitsMultipleOf(2), transform: Transform::Split(2) },
+                Rule { predicate: Predicate::Always, transform: Transform::Multiply(2024) },
+            ],
+        }
+    }
+
+    // One rule per line, "# comments" and blank lines are ignored:
+    //   base 10
+    //   value 0 -> value 1
+    //   digits-multiple-of 2 -> split 2
+    //   always -> multiply 2024
+    // Transforms are value, split, multiply and add.
+    fn parse(text: &str) -> Result<RuleSet, String> {
+        let mut base = 10;
+        let mut rules = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |msg: &str| format!("line {}: {} in '{}'", line_no + 1, msg, line);
+            if let Some(b) = line.strip_prefix("base ") {
+                base = match b.trim().parse::<u128>() {
+                    Ok(b) if b >= 2 => b,
+                    _ => return Err(error("base must be a number of at least 2")),
+                };
+                continue;
+            }
+            let (predicate, transform) = line.split_once("->").ok_or_else(|| error("expected 'predicate -> transform'"))?;
+            let predicate: Vec<&str> = predicate.split_whitespace().collect();
+            let transform: Vec<&str> = transform.split_whitespace().collect();
+            let number = |s: Option<&&str>| s.and_then(|n| n.parse::<u128>().ok()).ok_or_else(|| error("expected a number"));
+
+            let predicate = match predicate.first().copied() {
+                Some("value") => Predicate::Value(number(predicate.get(1))?),
+                Some("digits-multiple-of") => match u32::try_from(number(predicate.get(1))?) {
+                    Ok(k) if k >= 1 => Predicate::DigitsMultipleOf(k),
+                    _ => return Err(error("digit multiple must be at least 1")),
+                },
+                Some("always") => Predicate::Always,
+                _ => return Err(error("unknown predicate")),
+            };
+            let transform = match transform.first().copied() {
+                Some("value") => Transform::Value(number(transform.get(1))?),
+                Some("split") => match u32::try_from(number(transform.get(1))?) {
+                    Ok(k) if k >= 1 => Transform::Split(k),
+                    _ => return Err(error("split must be into at least 1 part")),
+                },
+                Some("multiply") => Transform::Multiply(number(transform.get(1))?),
+                Some("add") => Transform::Add(number(transform.get(1))?),
+                _ => return Err(error("unknown transform")),
+            };
+            rules.push(Rule { predicate, transform });
         }
-        let num_string = s.to_string();
-        if num_string.len() % 2 == 0 {
-            let middle = num_string.len() / 2;
-            new_stones.push(num_string[..middle].parse::<usize>().unwrap_or(0));
-            new_stones.push(num_string[middle..].parse::<usize>().unwrap_or(0));
-        }
-        else {
-            new_stones.push(s * 2024)
-        }
-    }
-    new_stones
-}
-
-fn blink_hash (stones: &HashMap<usize, usize>) -> HashMap<usize, usize> {
-    let mut new_stones = HashMap::with_capacity(stones.len());
-    for (&stone, &count) in stones {
-        match stone {
-            // X 0 stones transform to X 1 stones
-            0 => *new_stones.entry(1).or_insert(0) += count,
-            _ => {
-                let num_string = stone.to_string();
-                if num_string.len() % 2 == 0 {
-                    let middle = num_string.len() / 2;
-                    let a = num_string[..middle].parse::<usize>().unwrap_or(0);
-                    let b = num_string[middle..].parse::<usize>().unwrap_or(0);
-                    *new_stones.entry(a).or_insert(0) += count;
-                    *new_stones.entry(b).or_insert(0) += count;
+        Ok(RuleSet { base, rules })
+    }
+
+    fn digit_count(&self, n: u128) -> u32 {
+        n.checked_ilog(self.base).map_or(1, |d| d + 1)
+    }
+
+    // The stones a single stone turns into after one blink,
+    // or None if a new value doesn't fit in a u128.
+    // A stone that matches no rule is left as it is.
+    fn apply(&self, stone: u128) -> Option<Vec<u128>> {
+        let digits = self.digit_count(stone);
+        let rule = self.rules.iter().find(|r| match r.predicate {
+            Predicate::Value(v) => stone == v,
+            Predicate::DigitsMultipleOf(k) => digits.is_multiple_of(k),
+            Predicate::Always => true,
+        });
+        let Some(rule) = rule else {
+            return Some(vec![stone]);
+        };
+        match rule.transform {
+            Transform::Value(v) => Some(vec![v]),
+            Transform::Split(parts) => {
+                // digits is a multiple of parts unless the rule didn't check,
+                // in which case the leftover digits go to the first part
+                let width = self.base.checked_pow(digits / parts)?;
+                let mut rest = stone;
+                let mut split = Vec::with_capacity(parts as usize);
+                for _ in 1..parts {
+                    split.push(rest % width);
+                    rest /= width;
                 }
-                else {
-                    *new_stones.entry(stone * 2024).or_insert(0) += count;
+                split.push(rest);
+                split.reverse();
+                Some(split)
+            }
+            Transform::Multiply(m) => stone.checked_mul(m).map(|s| vec![s]),
+            Transform::Add(a) => stone.checked_add(a).map(|s| vec![s]),
+        }
+    }
+}
+
+struct StoneCounter {
+    rules: RuleSet,
+    // what each stone seen so far turns into after one blink
+    expansions: HashMap<u128, Option<Vec<u128>>>,
+}
+
+impl StoneCounter {
+    fn new(rules: RuleSet) -> Self {
+        StoneCounter { rules, expansions: HashMap::new() }
+    }
+
+    fn expand(&mut self, stone: u128) -> Option<Vec<u128>> {
+        self.expansions.entry(stone).or_insert_with(|| self.rules.apply(stone)).clone()
+    }
+
+    // Every stone value that can appear within the given number of blinks
+    fn closure(&mut self, stones: &[u128], blinks: usize) -> Vec<u128> {
+        let mut seen: HashSet<u128> = stones.iter().copied().collect();
+        let mut values: Vec<u128> = seen.iter().copied().collect();
+        let mut frontier = values.clone();
+        for _ in 0..blinks {
+            let mut next = Vec::new();
+            for stone in frontier {
+                for child in self.expand(stone).unwrap_or_default() {
+                    if seen.insert(child) {
+                        next.push(child);
+                    }
                 }
             }
+            if next.is_empty() {
+                break;
+            }
+            values.extend(&next);
+            frontier = next;
+        }
+        values
+    }
+
+    // count(stone, b) is the number of stones a single stone becomes after b blinks.
+    // It is memoised for every value in the closure, one blink level at a time,
+    // so only the previous level needs to be kept.
+    fn count(&mut self, stones: &[u128], blinks: usize) -> Count {
+        let values = self.closure(stones, blinks);
+        let index: HashMap<u128, usize> = values.iter().enumerate().map(|(i, &v)| (v, i)).collect();
+        // None where the value would overflow, or the child was never reached
+        let children: Vec<Option<Vec<usize>>> = values.iter()
+            .map(|&v| {
+                self.expand(v)?.iter().map(|c| index.get(c).copied()).collect()
+            })
+            .collect();
+
+        let mut counts: Vec<Count> = vec![Some(1); values.len()];
+        for _ in 0..blinks {
+            counts = children.iter()
+                .map(|c| c.as_ref()?.iter().try_fold(0u128, |acc, &i| acc.checked_add(counts[i]?)))
+                .collect();
+        }
+        stones.iter().try_fold(0u128, |acc, s| acc.checked_add(counts[index[s]]?))
+    }
+}
+
+// How many different stone values are on the line after the given number of blinks
+fn distinct_after(counter: &mut StoneCounter, stones: &[u128], blinks: usize) -> usize {
+    let mut current: HashSet<u128> = stones.iter().copied().collect();
+    for _ in 0..blinks {
+        current = current.iter()
+            .flat_map(|&s| counter.expand(s).unwrap_or_default())
+            .collect();
+    }
+    current.len()
+}
+
+// The closed set of stone values reachable from the input as a sparse transition matrix:
+// row i lists the values that values[i] turns into after one blink
+struct Transitions {
+    values: Vec<u128>,
+    index: HashMap<u128, usize>,
+    rows: Vec<Vec<usize>>,
+    // number of blinks after which no new values appear
+    settled_after: usize,
+}
+
+impl StoneCounter {
+    fn transitions(&mut self, stones: &[u128], max_values: usize) -> Result<Transitions, String> {
+        let mut values: Vec<u128> = Vec::new();
+        let mut index: HashMap<u128, usize> = HashMap::new();
+        for &s in stones {
+            index.entry(s).or_insert_with(|| {
+                values.push(s);
+                values.len() - 1
+            });
+        }
+        let mut rows: Vec<Vec<usize>> = Vec::new();
+        let mut settled_after = 0;
+        // values are added breadth first, so each new level starts where the last one ended
+        let mut level_end = values.len();
+        while rows.len() < values.len() {
+            let value = values[rows.len()];
+            let children = self.expand(value).ok_or_else(|| format!("stone {} grows past u128::MAX", value))?;
+            let mut row = Vec::with_capacity(children.len());
+            for child in children {
+                let i = *index.entry(child).or_insert_with(|| {
+                    values.push(child);
+                    values.len() - 1
+                });
+                row.push(i);
+            }
+            rows.push(row);
+            if values.len() > max_values {
+                return Err(format!("more than {} values are reachable within {} blinks", max_values, settled_after + 1));
+            }
+            if rows.len() == level_end && values.len() > level_end {
+                settled_after += 1;
+                level_end = values.len();
+            }
         }
+        Ok(Transitions { values, index, rows, settled_after })
     }
-    new_stones
+}
+
+fn mul_mod(a: u64, b: u64, modulus: u64) -> u64 {
+    (a as u128 * b as u128 % modulus as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exp: u64, modulus: u64) -> u64 {
+    let mut result = 1 % modulus;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul_mod(result, base, modulus);
+        }
+        base = mul_mod(base, base, modulus);
+        exp >>= 1;
+    }
+    result
+}
+
+// Deterministic Miller-Rabin for every u64
+fn is_prime(n: u64) -> bool {
+    const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    if let Some(&w) = WITNESSES.iter().find(|&&w| n.is_multiple_of(w)) {
+        return n == w;
+    }
+    let d = (n - 1) >> (n - 1).trailing_zeros();
+    WITNESSES.iter().all(|&a| {
+        let mut x = pow_mod(a, d, n);
+        let mut d = d;
+        while d != n - 1 && x != 1 && x != n - 1 {
+            x = mul_mod(x, x, n);
+            d <<= 1;
+        }
+        x == n - 1 || d & 1 == 1
+    })
+}
+
+// Shortest recurrence s[i] = sum of c[j] * s[i - j - 1] generating the sequence, modulo a prime
+fn berlekamp_massey(s: &[u64], modulus: u64) -> Vec<u64> {
+    let n = s.len();
+    let mut c = vec![0u64; n + 1];
+    let mut b = vec![0u64; n + 1];
+    c[0] = 1;
+    b[0] = 1;
+    let (mut length, mut shift, mut last_discrepancy) = (0, 1, 1);
+    for i in 0..n {
+        let mut d = s[i];
+        for j in 1..=length {
+            d = (d + mul_mod(c[j], s[i - j], modulus)) % modulus;
+        }
+        if d == 0 {
+            shift += 1;
+            continue;
+        }
+        let previous = c.clone();
+        let coef = mul_mod(d, pow_mod(last_discrepancy, modulus - 2, modulus), modulus);
+        for j in shift..=n {
+            c[j] = (c[j] + modulus - mul_mod(coef, b[j - shift], modulus)) % modulus;
+        }
+        if 2 * length <= i {
+            length = i + 1 - length;
+            b = previous;
+            last_discrepancy = d;
+            shift = 1;
+        } else {
+            shift += 1;
+        }
+    }
+    c[1..=length].iter().map(|&x| (modulus - x) % modulus).collect()
+}
+
+// a * b reduced by x^L = sum of recurrence[j] * x^(L - j - 1)
+fn poly_mul_mod(a: &[u64], b: &[u64], recurrence: &[u64], modulus: u64) -> Vec<u64> {
+    let length = recurrence.len();
+    let mut product = vec![0u64; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        if x == 0 {
+            continue;
+        }
+        for (j, &y) in b.iter().enumerate() {
+            product[i + j] = (product[i + j] + mul_mod(x, y, modulus)) % modulus;
+        }
+    }
+    for d in (length..product.len()).rev() {
+        let t = product[d];
+        if t == 0 {
+            continue;
+        }
+        for (j, &r) in recurrence.iter().enumerate() {
+            product[d - j - 1] = (product[d - j - 1] + mul_mod(t, r, modulus)) % modulus;
+        }
+    }
+    product.truncate(length);
+    product
+}
+
+struct Analysis {
+    transitions: Transitions,
+    recurrence_order: usize,
+    stones: u64,
+}
+
+// Count the stones after any number of blinks modulo a prime. The count is
+// s[k] = (start counts) . M^k . (all ones) for the n x n transition matrix M.
+// Rather than squaring a dense matrix, M^blinks is reduced by a recurrence,
+// which only needs sparse matrix-vector products.
+//
+// This is exact, not just likely: M's characteristic polynomial (mod p) has
+// degree n and sends M to zero, so s obeys a recurrence of order at most n.
+// Berlekamp-Massey given 2n terms of a sequence whose shortest recurrence has
+// order L <= n returns that recurrence, and it is the only one of order <= n
+// matching those terms, so it holds for every later term too. Nothing is
+// random here; only finding M's own minimal polynomial from random projections
+// (as Wiedemann's method does) could fail.
+fn analyse(counter: &mut StoneCounter, stones: &[u128], blinks: u64, modulus: u64, max_values: usize) -> Result<Analysis, String> {
+    let transitions = counter.transitions(stones, max_values)?;
+    let mut start = vec![0u64; transitions.values.len()];
+    for s in stones {
+        let i = transitions.index[s];
+        start[i] = (start[i] + 1) % modulus;
+    }
+
+    // count after k blinks for k < 2n pins down a recurrence of order at most n
+    let terms = 2 * transitions.values.len();
+    let mut sequence = Vec::with_capacity(terms);
+    let mut counts = vec![1 % modulus; transitions.values.len()];
+    for k in 0..terms {
+        sequence.push(start.iter().zip(&counts).fold(0, |acc, (&w, &c)| (acc + mul_mod(w, c, modulus)) % modulus));
+        if k as u64 == blinks {
+            return Ok(Analysis { transitions, recurrence_order: 0, stones: sequence[k] });
+        }
+        counts = transitions.rows.iter()
+            .map(|row| row.iter().fold(0, |acc, &i| (acc + counts[i]) % modulus))
+            .collect();
+    }
+
+    let recurrence = berlekamp_massey(&sequence, modulus);
+    let order = recurrence.len();
+    if order == 0 {
+        return Ok(Analysis { transitions, recurrence_order: 0, stones: 0 });
+    }
+    // x^blinks reduced by the recurrence gives the count as a combination of the first terms
+    let mut result = poly_mul_mod(&[1], &[1], &recurrence, modulus);
+    let mut base = poly_mul_mod(&[0, 1], &[1], &recurrence, modulus);
+    let mut exp = blinks;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = poly_mul_mod(&result, &base, &recurrence, modulus);
+        }
+        base = poly_mul_mod(&base, &base, &recurrence, modulus);
+        exp >>= 1;
+    }
+    let stones = result.iter().zip(&sequence).fold(0, |acc, (&r, &s)| (acc + mul_mod(r, s, modulus)) % modulus);
+    Ok(Analysis { transitions, recurrence_order: order, stones })
+}
+
+fn describe(count: Count) -> String {
+    match count {
+        Some(n) => n.to_string(),
+        None => "more than u128::MAX".to_string(),
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
 }
 
 fn main() -> io::Result<()> {
 
+    // Options:
+    //   --blinks <n>      also count the stones after any number of blinks
+    //   --rules <file>    load the stone rules from a file instead of the puzzle's
+    //   --analyse <n>     count the stones after n blinks modulo a prime using the
+    //                     transition matrix of every reachable stone value
+    //   --modulus <p>     prime for --analyse (default 1000000007)
+    //   --max-values <n>  give up on --analyse past this many values (default 10000)
+    let args: Vec<String> = env::args().skip(1).collect();
+    let number_arg = |name: &str| -> io::Result<Option<u64>> {
+        match args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)) {
+            Some(n) => n.parse().map(Some).map_err(|_| invalid(format!("{} needs a number", name))),
+            None => Ok(None),
+        }
+    };
+    let blinks = number_arg("--blinks")?.map(|b| b as usize);
+    let analyse_blinks = number_arg("--analyse")?;
+    let modulus = number_arg("--modulus")?.unwrap_or(1_000_000_007);
+    if !is_prime(modulus) {
+        return Err(invalid(format!("--modulus {} is not prime", modulus)));
+    }
+    let max_values = number_arg("--max-values")?.unwrap_or(10_000) as usize;
+
+    let rules = match args.iter().position(|a| a == "--rules").and_then(|i| args.get(i + 1)) {
+        Some(rules_path) => RuleSet::parse(&fs::read_to_string(rules_path)?)
+            .map_err(invalid)?,
+        None => RuleSet::puzzle(),
+    };
+
     // Open the file
     let path = Path::new("input");
     //let path = Path::new("sample");
     //let path = Path::new("sample2");
-    let file = File::open(&path)?;
+    let file = File::open(path)?;
     let reader = io::BufReader::new(file);
 
-    let mut stones: Vec<usize> = Vec::new();
+    // The stones are all on the first line
+    let line = reader.lines().next().transpose()?.unwrap_or_default();
+    let stones: Vec<u128> = line.split_whitespace().filter_map(|s| s.parse::<u128>().ok()).collect();
 
-    // Process each line
-    for line in reader.lines() {
-        let line = line?;
-        stones = line.split_whitespace().filter_map(|s| s.parse::<usize>().ok()).collect();
-        break;
-    }
+    let mut counter = StoneCounter::new(rules);
 
-    // Create hash of each stone for p2
-    let mut stones_map: HashMap<usize, usize> = HashMap::with_capacity(stones.len());
-    for s in &stones {
-        *stones_map.entry(*s).or_insert(0) = 1;
+    if let Some(blinks) = blinks {
+        println!("After {} blinks: {}", blinks, describe(counter.count(&stones, blinks)));
     }
 
-    for _ in 0..25 {
-        stones = blink(stones);
-    }
-    let part1_answer: usize = stones.len();
-
-    for _ in 0..75 {
-        stones_map = blink_hash(&stones_map);
+    if let Some(blinks) = analyse_blinks {
+        match analyse(&mut counter, &stones, blinks, modulus, max_values) {
+            Ok(analysis) => {
+                let t = &analysis.transitions;
+                println!("{} distinct stone values, no new values after {} blinks", t.values.len(), t.settled_after);
+                println!("Transition matrix: {0}x{0} with {1} non-zero entries", t.values.len(), t.rows.iter().map(|r| r.len()).sum::<usize>());
+                if analysis.recurrence_order > 0 {
+                    println!("Counts follow a linear recurrence of order {}", analysis.recurrence_order);
+                }
+                println!("After {} blinks: {} (mod {})", blinks, analysis.stones, modulus);
+            }
+            Err(e) => println!("Could not analyse the stones: {}", e),
+        }
     }
-    println!("There were {} different stones after 75 blinks", stones_map.len());
-    let part2_answer: usize = stones_map.values().sum();
-
 
+    let part1_answer = describe(counter.count(&stones, 25));
 
+    println!("There were {} different stones after 75 blinks", distinct_after(&mut counter, &stones, 75));
+    let part2_answer = describe(counter.count(&stones, 75));
 
-    println!("Part1: {:?}", part1_answer);
-    println!("Part2: {:?}", part2_answer);
+    println!("Part1: {}", part1_answer);
+    println!("Part2: {}", part2_answer);
 
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 76 values are reachable from the sample, so past 152 blinks the count
+    // comes from the recurrence, and before about 200 it still fits in a u128
+    #[test]
+    fn analysis_matches_exact_count() {
+        let stones = [125, 17];
+        let modulus = 1_000_000_007;
+        for blinks in [0, 25, 75, 151, 152, 153, 175, 190] {
+            let exact = StoneCounter::new(RuleSet::puzzle()).count(&stones, blinks).expect("fits in a u128");
+            let analysis = analyse(&mut StoneCounter::new(RuleSet::puzzle()), &stones, blinks as u64, modulus, 10_000)
+                .expect("sample settles");
+            assert_eq!(analysis.stones as u128, exact % modulus as u128, "after {} blinks", blinks);
+        }
+    }
+}
@@ -1,160 +1,319 @@
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
-//use std::collections::HashMap;
+use std::env;
+use std::collections::HashMap;
 //use regex::Regex;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-struct Point {
-    x: isize,
-    y: isize,
+// Union-find over cell indices, with path halving so there is no recursion
+struct DisjointSet {
+    parent: Vec<u32>,
 }
-const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (0, -1), (-1, 0)];
-
-// DFS function for flood fill
-fn dfs(
-    grid: &Vec<Vec<char>>,
-    visited: &mut Vec<Vec<bool>>,
-    start: Point,
-    current_char: char,
-    region: &mut Vec<Point>,
-) {
-    let x = start.x;
-    let y = start.y;
-
-    // Boundary and visit checks
-    if y < 0 || y >= grid.len() as isize || x < 0 || x >= grid[0].len() as isize {
-        return;
+
+impl DisjointSet {
+    fn new(size: usize) -> Self {
+        DisjointSet { parent: (0..size as u32).collect() }
     }
-    if visited[y as usize][x as usize] || grid[y as usize][x as usize] != current_char {
-        return;
+
+    fn find(&mut self, mut i: u32) -> u32 {
+        while self.parent[i as usize] != i {
+            let grandparent = self.parent[self.parent[i as usize] as usize];
+            self.parent[i as usize] = grandparent;
+            i = grandparent;
+        }
+        i
     }
 
-    // Mark as visited and add to region
-    visited[y as usize][x as usize] = true;
-    region.push(Point { x: x, y: y});
-
-    // Recur in all directions
-    for (dx, dy) in DIRECTIONS.iter() {
-        dfs(
-            grid,
-            visited,
-            Point {
-                x: (x + dx),
-                y: (y + dy),
-            },
-            current_char,
-            region,
-        );
+    fn union(&mut self, a: u32, b: u32) {
+        let (a, b) = (self.find(a), self.find(b));
+        // keep the earlier cell as the root so labels follow reading order
+        if a < b {
+            self.parent[b as usize] = a;
+        } else if b < a {
+            self.parent[a as usize] = b;
+        }
     }
 }
 
-fn find_regions(grid: &Vec<Vec<char>>) -> Vec<Vec<Point>> {
-    let mut visited = vec![vec![false; grid[0].len()]; grid.len()];
-    let mut regions: Vec<Vec<Point>> = Vec::new();
-
-    for y in 0..grid.len() {
-        for x in 0..grid[0].len() {
-            if !visited[y][x] {
-                let mut region: Vec<Point> = Vec::new();
-                dfs(&grid, &mut visited, Point { x: x as isize, y: y as isize }, grid[y][x], &mut region);
+// Region label of every cell, numbered in the order each region is first met reading the grid
+struct Labels {
+    width: usize,
+    height: usize,
+    cells: Vec<u32>,
+    count: usize,
+}
 
-                if !region.is_empty() {
-                    regions.push(region);
-                }
-            }
+impl Labels {
+    fn get(&self, x: isize, y: isize) -> Option<u32> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
         }
+        Some(self.cells[y as usize * self.width + x as usize])
     }
-    regions
 }
 
-fn find_perimeter(grid: &Vec<Vec<char>>, p: &Point) -> usize {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Connectivity {
+    // plots join their region through shared edges only
+    Four,
+    // plots touching at a corner join the same region too
+    Eight,
+}
 
-    let mut perimeters: usize = 0;
-    let identifier: char = grid[p.y as usize][p.x as usize];
-    for (dx, dy) in DIRECTIONS.iter() {
-        let new_x = p.x + dx;
-        let new_y = p.y + dy;
-        if new_x < 0 || new_x >= grid[0].len() as isize || new_y < 0 || new_y >= grid.len() as isize {
-            perimeters += 1;
-        }
-        else if grid[new_y as usize][new_x as usize] != identifier {
-            perimeters += 1;
+impl Connectivity {
+    fn parse(s: &str) -> Option<Connectivity> {
+        match s {
+            "4" => Some(Connectivity::Four),
+            "8" => Some(Connectivity::Eight),
+            _ => None,
         }
     }
-    perimeters
 }
 
-fn count_sides( region: &Vec<Point>) -> usize {
-    let mut sides: usize = 0;
-
-    for p in region {
-        // Check convex corner.
-        // ?B
-        // BA
-        if !region.contains(&Point{ x: p.x, y: p.y - 1 }) && !region.contains(&Point{ x: p.x -1, y: p.y }) {
-            sides += 1;
-        }
-        // BA
-        // AA
-        else if region.contains(&Point{ x: p.x, y: p.y - 1}) && region.contains(&Point{ x: p.x -1, y: p.y}) {
-            if !region.contains(&Point{ x: p.x - 1, y: p.y - 1}) {
-                sides += 1;
+// Groups of plants to treat as one crop, e.g. "Aa,Bb". Each plant becomes the first of its group.
+fn merge_plants(grid: &[Vec<char>], groups: &str) -> Vec<Vec<char>> {
+    let mut canonical: HashMap<char, char> = HashMap::new();
+    for group in groups.split(',') {
+        if let Some(first) = group.chars().next() {
+            for plant in group.chars() {
+                canonical.insert(plant, first);
             }
         }
-        // Check convex corner.
-        // B?
-        // AB
-        if !region.contains(&Point{ x: p.x, y: p.y - 1 }) && !region.contains(&Point{ x: p.x + 1, y: p.y }) {
-            sides += 1;
-        }
-        // AB
-        // AA
-        else if region.contains(&Point{ x: p.x, y: p.y - 1}) && region.contains(&Point{ x: p.x + 1, y: p.y}) {
-            if !region.contains(&Point{ x: p.x + 1, y: p.y - 1}) {
-                sides += 1;
+    }
+    grid.iter()
+        .map(|row| row.iter().map(|c| *canonical.get(c).unwrap_or(c)).collect())
+        .collect()
+}
+
+// Scanline labelling: join each plot to the plots before it in reading order
+// that it touches and that have the same plant
+fn label_regions(grid: &[Vec<char>], connectivity: Connectivity) -> Labels {
+    let height = grid.len();
+    let width = grid.first().map_or(0, |row| row.len());
+    let mut sets = DisjointSet::new(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) as u32;
+            if x > 0 && grid[y][x - 1] == grid[y][x] {
+                sets.union(i - 1, i);
+            }
+            if y > 0 && grid[y - 1][x] == grid[y][x] {
+                sets.union(i - width as u32, i);
+            }
+            if connectivity == Connectivity::Eight && y > 0 {
+                if x > 0 && grid[y - 1][x - 1] == grid[y][x] {
+                    sets.union(i - width as u32 - 1, i);
+                }
+                if x + 1 < width && grid[y - 1][x + 1] == grid[y][x] {
+                    sets.union(i - width as u32 + 1, i);
+                }
             }
         }
-        // Check convex corner.
-        // AB
-        // B?
-        if !region.contains(&Point{ x: p.x, y: p.y + 1 }) && !region.contains(&Point{ x: p.x + 1, y: p.y }) {
-            sides += 1;
+    }
+
+    // Roots are the first cell of their region, so they are seen before any other member
+    let mut cells = vec![0u32; width * height];
+    let mut count = 0;
+    for i in 0..width * height {
+        let root = sets.find(i as u32) as usize;
+        if root == i {
+            cells[i] = count as u32;
+            count += 1;
+        } else {
+            cells[i] = cells[root];
         }
-        // AA
-        // AB
-        else if region.contains(&Point{ x: p.x, y: p.y + 1}) && region.contains(&Point{ x: p.x + 1, y: p.y}) {
-            if !region.contains(&Point{ x: p.x + 1, y: p.y + 1}) {
-                sides += 1;
+    }
+    Labels { width, height, cells, count }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BoundingBox {
+    min_x: usize,
+    min_y: usize,
+    max_x: usize,
+    max_y: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RegionStats {
+    plant: char,
+    area: usize,
+    perimeter: usize,
+    sides: usize,
+    bounds: BoundingBox,
+    // pockets of other plants (or several regions together) cut off from the outside
+    holes: usize,
+}
+
+// Fence and sides mean the same with either connectivity: fence runs along every
+// edge between a region's plot and one that isn't in it, and a region that touches
+// itself only at a corner has its boundary pinched there, making two corners.
+fn region_stats(grid: &[Vec<char>], labels: &Labels, connectivity: Connectivity) -> Vec<RegionStats> {
+    let empty = BoundingBox { min_x: usize::MAX, min_y: usize::MAX, max_x: 0, max_y: 0 };
+    let mut stats = vec![RegionStats { plant: ' ', area: 0, perimeter: 0, sides: 0, bounds: empty, holes: 0 }; labels.count];
+    for (i, &label) in labels.cells.iter().enumerate() {
+        let (x, y) = (i % labels.width, i / labels.width);
+        let region = &mut stats[label as usize];
+        region.plant = grid[y][x];
+        region.area += 1;
+        region.bounds.min_x = region.bounds.min_x.min(x);
+        region.bounds.min_y = region.bounds.min_y.min(y);
+        region.bounds.max_x = region.bounds.max_x.max(x);
+        region.bounds.max_y = region.bounds.max_y.max(y);
+
+        // Every edge facing a different region (or the outside) is fence
+        let (x, y) = (x as isize, y as isize);
+        region.perimeter += [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)].iter()
+            .filter(|&&(nx, ny)| labels.get(nx, ny) != Some(label))
+            .count();
+    }
+
+    // A region has as many sides as corners. Look at every 2x2 window, including
+    // ones hanging off the edge: a region holding one or three of its cells has
+    // a corner there, and one holding two diagonally opposite cells has two.
+    // The same counts give the region's Euler number (one minus its holes) as
+    // (windows with one cell - windows with three +/- 2 * diagonal windows) / 4,
+    // adding diagonals for 4-connected regions and subtracting them for 8-connected.
+    let diagonal = match connectivity {
+        Connectivity::Four => 2,
+        Connectivity::Eight => -2,
+    };
+    let mut euler = vec![0isize; labels.count];
+    for y in 0..=labels.height as isize {
+        for x in 0..=labels.width as isize {
+            let window = [labels.get(x - 1, y - 1), labels.get(x, y - 1), labels.get(x - 1, y), labels.get(x, y)];
+            for (i, label) in window.iter().enumerate() {
+                let Some(label) = *label else { continue };
+                // only count each region once per window
+                if window[..i].contains(&Some(label)) {
+                    continue;
+                }
+                let (corners, quad) = match window.iter().filter(|&&l| l == Some(label)).count() {
+                    1 => (1, 1),
+                    3 => (1, -1),
+                    2 if window[0] == window[3] || window[1] == window[2] => (2, diagonal),
+                    _ => (0, 0),
+                };
+                stats[label as usize].sides += corners;
+                euler[label as usize] += quad;
             }
         }
-        // Check convex corner.
-        // BA
-        // ?B
-        if !region.contains(&Point{ x: p.x, y: p.y + 1 }) && !region.contains(&Point{ x: p.x -1, y: p.y }) {
-            sides += 1;
+    }
+    for (region, e) in stats.iter_mut().zip(euler) {
+        region.holes = (1 - e / 4) as usize;
+    }
+    stats
+}
+
+// For each region, the innermost region it sits in a hole of, if any.
+// Only regions with holes are searched: the cells of their bounding box that
+// can't reach its edge without crossing the region are in one of the holes.
+// Holes in 4-connected regions can leak out through corners, holes in
+// 8-connected regions can't.
+fn enclosing_regions(labels: &Labels, stats: &[RegionStats], connectivity: Connectivity) -> Vec<Option<usize>> {
+    let mut enclosed_by: Vec<Option<usize>> = vec![None; stats.len()];
+    let box_area = |b: &BoundingBox| (b.max_x - b.min_x + 1) * (b.max_y - b.min_y + 1);
+    for (outer, region) in stats.iter().enumerate().filter(|(_, r)| r.holes > 0) {
+        let b = region.bounds;
+        let (width, height) = (b.max_x - b.min_x + 1, b.max_y - b.min_y + 1);
+        let label_at = |x: usize, y: usize| labels.cells[(b.min_y + y) * labels.width + b.min_x + x] as usize;
+
+        // Flood from the box edge through cells outside the region
+        let mut outside = vec![false; width * height];
+        let mut stack: Vec<(usize, usize)> = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let on_edge = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+                if on_edge && label_at(x, y) != outer {
+                    outside[y * width + x] = true;
+                    stack.push((x, y));
+                }
+            }
         }
-        // AA
-        // BA
-        else if region.contains(&Point{ x: p.x, y: p.y + 1}) && region.contains(&Point{ x: p.x - 1, y: p.y}) {
-            if !region.contains(&Point{ x: p.x - 1, y: p.y + 1}) {
-                sides += 1;
+        while let Some((x, y)) = stack.pop() {
+            for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                    let corner = nx != x && ny != y;
+                    if corner && connectivity == Connectivity::Eight {
+                        continue;
+                    }
+                    if !outside[ny * width + nx] && label_at(nx, ny) != outer {
+                        outside[ny * width + nx] = true;
+                        stack.push((nx, ny));
+                    }
+                }
             }
-        }}
+        }
 
+        for y in 0..height {
+            for x in 0..width {
+                let inner = label_at(x, y);
+                if inner == outer || outside[y * width + x] {
+                    continue;
+                }
+                // an enclosing region's box contains the boxes of those inside it
+                if enclosed_by[inner].is_none_or(|current| box_area(&stats[current].bounds) > box_area(&b)) {
+                    enclosed_by[inner] = Some(outer);
+                }
+            }
+        }
+    }
+    enclosed_by
+}
 
-    sides
+fn render_report(stats: &[RegionStats], enclosed_by: &[Option<usize>]) -> String {
+    let mut output = format!("{:>6} {:>5} {:>8} {:>9} {:>6} {:>23} {:>5} {:>11}\n",
+        "region", "plant", "area", "perimeter", "sides", "bounding box", "holes", "enclosed by");
+    for (id, (region, enclosure)) in stats.iter().zip(enclosed_by).enumerate() {
+        let b = region.bounds;
+        let bounds = format!("({},{})-({},{})", b.min_x, b.min_y, b.max_x, b.max_y);
+        let enclosure = enclosure.map_or("-".to_string(), |e| e.to_string());
+        output.push_str(&format!("{:>6} {:>5} {:>8} {:>9} {:>6} {:>23} {:>5} {:>11}\n",
+            id, region.plant, region.area, region.perimeter, region.sides, bounds, region.holes, enclosure));
+    }
+    output
 }
 
+fn render_json(stats: &[RegionStats], enclosed_by: &[Option<usize>]) -> String {
+    let rows: Vec<String> = stats.iter().zip(enclosed_by).enumerate().map(|(id, (region, enclosure))| {
+        let b = region.bounds;
+        // plants are whatever characters the map uses, so escape the awkward ones
+        let plant = match region.plant {
+            '"' => "\\\"".to_string(),
+            '\\' => "\\\\".to_string(),
+            c if c.is_control() => format!("\\u{:04x}", c as u32),
+            c => c.to_string(),
+        };
+        format!("  {{\"region\": {}, \"plant\": \"{}\", \"area\": {}, \"perimeter\": {}, \"sides\": {}, \"bounding_box\": {{\"min_x\": {}, \"min_y\": {}, \"max_x\": {}, \"max_y\": {}}}, \"holes\": {}, \"enclosed_by\": {}}}",
+            id, plant, region.area, region.perimeter, region.sides, b.min_x, b.min_y, b.max_x, b.max_y, region.holes,
+            enclosure.map_or("null".to_string(), |e| e.to_string()))
+    }).collect();
+    format!("[\n{}\n]\n", rows.join(",\n"))
+}
 
 fn main() -> io::Result<()> {
 
+    // Options:
+    //   --report  print every region as a table
+    //   --json    print every region as JSON
+    //   --connectivity 4|8  whether plots touching at a corner join a region (default 4)
+    //   --merge <groups>    treat groups of plants as one crop, e.g. Aa,Bb
+    let args: Vec<String> = env::args().skip(1).collect();
+    let report = args.iter().any(|a| a == "--report");
+    let json = args.iter().any(|a| a == "--json");
+    let connectivity = match args.iter().position(|a| a == "--connectivity").and_then(|i| args.get(i + 1)) {
+        Some(c) => Connectivity::parse(c)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Connectivity must be 4 or 8, not '{}'", c)))?,
+        None => Connectivity::Four,
+    };
+    let merge = args.iter().position(|a| a == "--merge").and_then(|i| args.get(i + 1));
+
     // Open the file
     let path = Path::new("input");
     //let path = Path::new("sample1");
     //let path = Path::new("sample2");
     //let path = Path::new("sample3");
-    let file = File::open(&path)?;
+    let file = File::open(path)?;
     let reader = io::BufReader::new(file);
 
     let mut part1_answer: usize = 0;
@@ -163,15 +322,26 @@ fn main() -> io::Result<()> {
     // Process each line
     let grid: Vec<Vec<char>> = reader.lines().map(|line| line.expect("Could not read line").chars().collect()).collect();
 
-    let regions = find_regions(&grid);
-    for region in &regions {
-         let area: usize = region.len();
-         let mut perimeter: usize = 0;
-         for p in region {
-             perimeter += find_perimeter(&grid, p);
-         }
-         part1_answer += area * perimeter;
-         part2_answer += area * count_sides(region);
+    let grid = match merge {
+        Some(groups) => merge_plants(&grid, groups),
+        None => grid,
+    };
+
+    let labels = label_regions(&grid, connectivity);
+    let stats = region_stats(&grid, &labels, connectivity);
+    for region in &stats {
+        part1_answer += region.area * region.perimeter;
+        part2_answer += region.area * region.sides;
+    }
+
+    if report || json {
+        let enclosed_by = enclosing_regions(&labels, &stats, connectivity);
+        if report {
+            print!("{}", render_report(&stats, &enclosed_by));
+        }
+        if json {
+            print!("{}", render_json(&stats, &enclosed_by));
+        }
     }
 
     println!("Part1: {:?}", part1_answer);
@@ -179,4 +349,3 @@ fn main() -> io::Result<()> {
 
     Ok(())
 }
-
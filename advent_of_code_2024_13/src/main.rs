@@ -1,89 +1,276 @@
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
-//use std::collections::HashMap;
+use std::collections::{BinaryHeap, HashMap};
+use std::cmp::Reverse;
+use std::env;
 //use regex::Regex;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct Point {
-    x: isize,
-    y: isize,
+    x: i128,
+    y: i128,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Button {
+    label: String,
+    movement: Point,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct Machine {
-    A: Point,
-    B: Point,
+    buttons: Vec<Button>,
     prize: Point,
 }
 
-// Parsing function to extract values from the lines
-fn parse_claw_machine(lines: &[String]) -> Option<Machine> {
-    if lines.len() < 3 {
-        return None;
+// Tokens needed per press of each button, unless --costs says otherwise
+fn default_costs() -> HashMap<String, i128> {
+    HashMap::from([("A".to_string(), 3), ("B".to_string(), 1)])
+}
+
+// "A=3,B=1,C=7"
+fn parse_costs(list: &str) -> Result<HashMap<String, i128>, String> {
+    list.split(',').map(|entry| {
+        let (label, cost) = entry.split_once('=').ok_or_else(|| format!("expected label=cost in '{}'", entry))?;
+        match cost.trim().parse::<i128>() {
+            Ok(cost) if cost >= 0 => Ok((label.trim().to_string(), cost)),
+            _ => Err(format!("cost must be a non-negative number in '{}'", entry)),
+        }
+    }).collect()
+}
+
+fn machine_costs(m: &Machine, costs: &HashMap<String, i128>) -> Result<Vec<i128>, String> {
+    m.buttons.iter()
+        .map(|b| costs.get(&b.label).copied().ok_or_else(|| format!("no cost for button {}", b.label)))
+        .collect()
+}
+
+// Most presses the brute force search tries when the prize doesn't bound them
+const PRESS_LIMIT: i128 = 100;
+
+// A block of input lines that couldn't be read as a machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ParseError {
+    first_line: usize,
+    last_line: usize,
+    message: String,
+}
+
+// Read "X+94", "Y-12", "X=8400" or "X = -5" as an axis and a value
+fn parse_offset(part: &str) -> Result<(char, i128), String> {
+    let compact: String = part.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut chars = compact.chars();
+    let axis = match chars.next() {
+        Some(c @ ('X' | 'Y')) => c,
+        _ => return Err(format!("expected X or Y in '{}'", part.trim())),
+    };
+    let rest = chars.as_str();
+    let number = rest.strip_prefix('=').unwrap_or(rest);
+    // "+12" parses as 12, the prefix is only a sign
+    let value = number.parse::<i128>().map_err(|_| format!("bad offset '{}'", part.trim()))?;
+    Ok((axis, value))
+}
+
+fn parse_point(desc: &str) -> Result<Point, String> {
+    let mut x = None;
+    let mut y = None;
+    for part in desc.split(',') {
+        match parse_offset(part)? {
+            ('X', v) if x.is_none() => x = Some(v),
+            ('Y', v) if y.is_none() => y = Some(v),
+            (axis, _) => return Err(format!("{} given twice in '{}'", axis, desc.trim())),
+        }
+    }
+    match (x, y) {
+        (Some(x), Some(y)) => Ok(Point { x, y }),
+        _ => Err(format!("need both X and Y in '{}'", desc.trim())),
+    }
+}
+
+// Parsing function to extract values from the lines.
+// Lines look like "Button <label>: X+94, Y-34" and "Prize: X=8400, Y=5400"
+// in any order, with any spacing.
+fn parse_claw_machine(lines: &[String]) -> Result<Machine, String> {
+    let mut buttons: Vec<Button> = Vec::new();
+    let mut prize: Option<Point> = None;
+
+    for line in lines {
+        let (name, desc) = line.split_once(':').ok_or_else(|| format!("missing ':' in '{}'", line.trim()))?;
+        let name: Vec<&str> = name.split_whitespace().collect();
+        match name.as_slice() {
+            ["Button", label] => {
+                if buttons.iter().any(|b| b.label == *label) {
+                    return Err(format!("button {} given twice", label));
+                }
+                buttons.push(Button { label: label.to_string(), movement: parse_point(desc)? });
+            }
+            ["Prize"] => {
+                if prize.is_some() {
+                    return Err("more than one prize".to_string());
+                }
+                prize = Some(parse_point(desc)?);
+            }
+            _ => return Err(format!("unrecognised line '{}'", line.trim())),
+        }
+    }
+
+    let prize = prize.ok_or("no prize")?;
+    if buttons.is_empty() {
+        return Err("no buttons".to_string());
+    }
+    Ok(Machine { buttons, prize })
+}
+
+// Machines are separated by blank lines; each block that doesn't parse is reported
+fn parse_machines(lines: &[String]) -> (Vec<Machine>, Vec<ParseError>) {
+    let mut machines: Vec<Machine> = Vec::new();
+    let mut errors: Vec<ParseError> = Vec::new();
+    let mut block_start = 0;
+    for end in 0..=lines.len() {
+        if end < lines.len() && !lines[end].trim().is_empty() {
+            continue;
+        }
+        if end > block_start {
+            match parse_claw_machine(&lines[block_start..end]) {
+                Ok(machine) => machines.push(machine),
+                Err(message) => errors.push(ParseError { first_line: block_start + 1, last_line: end, message }),
+            }
+        }
+        block_start = end + 1;
     }
+    (machines, errors)
+}
 
-    // Extract Button A
-    let button_a = lines[0]
-        .strip_prefix("Button A: ")
-        .and_then(|desc| {
-            let parts: Vec<&str> = desc.split(", ").collect();
-            let dx = parts[0].strip_prefix("X+").and_then(|x| x.parse::<isize>().ok())?;
-            let dy = parts[1].strip_prefix("Y+").and_then(|y| y.parse::<isize>().ok())?;
-            Some(Point{ x: dx + 0, y: dy + 0})
-        })?;
-
-    // Extract Button B
-    let button_b = lines[1]
-        .strip_prefix("Button B: ")
-        .and_then(|desc| {
-            let parts: Vec<&str> = desc.split(", ").collect();
-            let dx = parts[0].strip_prefix("X+").and_then(|x| x.parse::<isize>().ok())?;
-            let dy = parts[1].strip_prefix("Y+").and_then(|y| y.parse::<isize>().ok())?;
-            Some(Point{ x: dx + 0, y: dy + 0})
-        })?;
-
-    // Extract Prize
-    let prize = lines[2]
-        .strip_prefix("Prize: ")
-        .and_then(|desc| {
-            let parts: Vec<&str> = desc.split(", ").collect();
-            let x = parts[0].strip_prefix("X=").and_then(|x| x.parse::<isize>().ok())?;
-            let y = parts[1].strip_prefix("Y=").and_then(|y| y.parse::<isize>().ok())?;
-            Some(Point{ x: x + 0, y: y + 0})
-        })?;
-    Some(Machine { A: button_a, B: button_b, prize: prize })
-}
-
-
-fn find_press_combinations(m: Machine) -> Vec<(isize, isize)> {
-    let x_target = m.prize.x;
-    let y_target = m.prize.y;
- 
-    println!("Finding presses for target: ({}, {})", x_target, y_target);
-
-    let mut found: Vec<(isize,isize)> = Vec::new();
+fn find_press_combinations(a: Point, b: Point, prize: Point) -> Vec<(i128, i128)> {
+    let x_target = prize.x;
+    let y_target = prize.y;
+
+    let mut found: Vec<(i128, i128)> = Vec::new();
     
+    // A can't be pressed past the prize along an axis where neither button
+    // moves backwards, otherwise fall back to the puzzle's limit of 100 presses
+    let max_a = if a.x > 0 && b.x >= 0 {
+        x_target / a.x
+    } else if a.y > 0 && b.y >= 0 {
+        y_target / a.y
+    } else {
+        PRESS_LIMIT
+    };
+
     // Try all possible combinations of presses for Button A
-    for n_a in 0..=x_target / m.A.x {
-        let remaining_x = x_target - n_a * m.A.x;
-        let remaining_y = y_target - n_a * m.A.y;
+    for n_a in 0..=max_a {
+        let remaining_x = x_target - n_a * a.x;
+        let remaining_y = y_target - n_a * a.y;
 
         // If the remaining distance is divisible by Button B's movement
-        if remaining_x % m.B.x == 0 && remaining_y % m.B.y == 0 {
-            let n_b_x = remaining_x / m.B.x;
-            let n_b_y = remaining_y / m.B.y;
+        let n_b = if b.x != 0 {
+            remaining_x / b.x
+        } else if b.y != 0 {
+            remaining_y / b.y
+        } else {
+            0
+        };
+        if n_b >= 0 && n_b * b.x == remaining_x && n_b * b.y == remaining_y {
+            found.push((n_a, n_b));
+        }
+    }
+    found
+}
 
-            if n_b_x == n_b_y && n_b_x >= 0 {
-                println!("P1: Press Button A {} times, Button B {} times", n_a, n_b_x);
-                found.push((n_a, n_b_x));
-            }
+fn cross(p: Point, q: Point) -> i128 {
+    p.x * q.y - p.y * q.x
+}
+
+fn floor_div(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    if a % b != 0 && (a < 0) != (b < 0) { q - 1 } else { q }
+}
+
+fn ceil_div(a: i128, b: i128) -> i128 {
+    -floor_div(-a, b)
+}
+
+// Returns (g, x, y) with a*x + b*y = g = gcd(a, b)
+fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    if b == 0 {
+        if a < 0 { (-a, -1, 0) } else { (a, 1, 0) }
+    } else {
+        let (g, x, y) = extended_gcd(b, a % b);
+        (g, y, x - (a / b) * y)
+    }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    extended_gcd(a, b).0
+}
+
+// Cheapest non-negative (u, v) with a*u + b*v = c
+fn cheapest_on_line(a: i128, b: i128, c: i128, cost_a: i128, cost_b: i128) -> Option<(i128, i128)> {
+    // With one button doing nothing along the line, never press it
+    if a == 0 && b == 0 {
+        return if c == 0 { Some((0, 0)) } else { None };
+    }
+    if a == 0 {
+        return if c % b == 0 && c / b >= 0 { Some((0, c / b)) } else { None };
+    }
+    if b == 0 {
+        return if c % a == 0 && c / a >= 0 { Some((c / a, 0)) } else { None };
+    }
+
+    let (g, x, y) = extended_gcd(a, b);
+    if c % g != 0 {
+        return None;
+    }
+    // Every solution is u = u0 + step_u*t, v = v0 - step_v*t for integer t
+    let (u0, v0) = (x * (c / g), y * (c / g));
+    let (step_u, step_v) = (b / g, a / g);
+
+    // Range of t keeping both press counts non-negative, None where unbounded
+    let mut low: Option<i128> = None;
+    let mut high: Option<i128> = None;
+    let mut bound = |lower: bool, t: i128| {
+        if lower {
+            low = Some(low.map_or(t, |l| l.max(t)));
+        } else {
+            high = Some(high.map_or(t, |h| h.min(t)));
+        }
+    };
+    // u0 + step_u*t >= 0
+    if step_u > 0 { bound(true, ceil_div(-u0, step_u)) } else { bound(false, floor_div(u0, -step_u)) }
+    // v0 - step_v*t >= 0
+    if step_v > 0 { bound(false, floor_div(v0, step_v)) } else { bound(true, ceil_div(-v0, -step_v)) }
+    if let (Some(l), Some(h)) = (low, high) {
+        if l > h {
+            return None;
         }
     }
 
-    if found.len() == 0 {
-        println!("P1: No valid combinations found.");
+    // The cost changes linearly with t, so the cheapest is at one end of the range
+    let slope = cost_a * step_u - cost_b * step_v;
+    let t = match (slope, low, high) {
+        (s, Some(l), _) if s >= 0 => l,
+        (s, _, Some(h)) if s <= 0 => h,
+        // cheaper forever in a direction with no bound, only with negative costs
+        _ => return None,
+    };
+    Some((u0 + step_u * t, v0 - step_v * t))
+}
+
+// Buttons that move along the same line: the prize has to be on that line too,
+// then it's a single equation in the two press counts
+fn solve_collinear(a: Point, b: Point, prize: Point, cost_a: i128, cost_b: i128) -> Option<(i128, i128)> {
+    if cross(a, prize) != 0 || cross(b, prize) != 0 {
+        return None;
+    }
+    let origin = Point { x: 0, y: 0 };
+    if a == origin && b == origin {
+        return if prize == origin { Some((0, 0)) } else { None };
+    }
+    if a.x != 0 || b.x != 0 {
+        cheapest_on_line(a.x, b.x, prize.x, cost_a, cost_b)
+    } else {
+        cheapest_on_line(a.y, b.y, prize.y, cost_a, cost_b)
     }
-    found
 }
 
 // There were no cases in my input where there were multiple solutions to any
@@ -92,86 +279,609 @@ fn find_press_combinations(m: Machine) -> Vec<(isize, isize)> {
 // be smarter - leaving the original part 1 solution find_press_combinations()
 // redo more sensibly with linear equation using cramer's Rule
 // https://en.wikipedia.org/wiki/Cramer%27s_rule
-fn solve_machine( m: Machine ) -> Option<(isize, isize)> {
+// Multiple solutions only happen when the buttons are collinear, which
+// solve_collinear() handles exactly.
+fn solve_two_buttons(a: Point, b: Point, prize: Point, cost_a: i128, cost_b: i128) -> Option<(i128, i128)> {
     // Determinant of the coefficient matrix
-    let det_a = m.A.x * m.B.y - m.B.x * m.A.y;
+    let det_a = cross(a, b);
 
     if det_a == 0 {
-        // If determinant is zero, no unique solution exists
+        solve_collinear(a, b, prize, cost_a, cost_b)
+    } else {
+        // Determinants of replacement matrices
+        let det_x = cross(prize, b);
+        let det_y = cross(a, prize);
+
+        // Solve for number of presses
+        let n_a = det_x / det_a;
+        let n_b = det_y / det_a;
+
+        // Check if the solution makes sense
+        if n_a >= 0 && n_b >= 0 && det_x % det_a == 0 && det_y % det_a == 0 {
+            Some((n_a, n_b))
+        } else {
+            None
+        }
+    }
+}
+
+// Row-reduced form of the machine's equations: one row per independent axis
+struct Equations {
+    rows: Vec<Vec<i128>>,
+    target: Vec<i128>,
+}
+
+// Drop an equation that says nothing new when every button moves along one line.
+// None if the prize isn't reachable even with fractional presses along that line.
+fn independent_equations(movements: &[Point], prize: Point) -> Option<Equations> {
+    let xs: Vec<i128> = movements.iter().map(|p| p.x).collect();
+    let ys: Vec<i128> = movements.iter().map(|p| p.y).collect();
+    let independent = movements.iter().enumerate()
+        .any(|(i, &p)| movements[i + 1..].iter().any(|&q| cross(p, q) != 0));
+    if independent {
+        return Some(Equations { rows: vec![xs, ys], target: vec![prize.x, prize.y] });
+    }
+    match movements.iter().find(|p| p.x != 0 || p.y != 0) {
+        Some(&direction) if cross(direction, prize) != 0 => None,
+        Some(&direction) if direction.x != 0 => Some(Equations { rows: vec![xs], target: vec![prize.x] }),
+        Some(_) => Some(Equations { rows: vec![ys], target: vec![prize.y] }),
+        None if prize == (Point { x: 0, y: 0 }) => Some(Equations { rows: Vec::new(), target: Vec::new() }),
+        None => None,
+    }
+}
+
+// A square set of buttons pinned down by the equations, kept as its adjugate so
+// that presses = adjugate . target / det stays in integers
+struct Basis {
+    columns: Vec<usize>,
+    det: i128,
+    adjugate: Vec<Vec<i128>>,
+}
+
+impl Basis {
+    fn new(eq: &Equations, columns: Vec<usize>) -> Option<Basis> {
+        let a = |r: usize, c: usize| eq.rows[r][columns[c]];
+        let (det, adjugate) = match columns.len() {
+            0 => (1, Vec::new()),
+            1 => (a(0, 0), vec![vec![1]]),
+            _ => (a(0, 0) * a(1, 1) - a(0, 1) * a(1, 0), vec![vec![a(1, 1), -a(0, 1)], vec![-a(1, 0), a(0, 0)]]),
+        };
+        if det == 0 {
+            return None;
+        }
+        // keep det positive so the scaled presses have the real presses' signs
+        let sign = det.signum();
+        let adjugate = adjugate.into_iter().map(|row| row.into_iter().map(|v| v * sign).collect()).collect();
+        Some(Basis { columns, det: det * sign, adjugate })
+    }
+
+    // det * the presses of the basis buttons that reach the target
+    fn scaled_presses(&self, target: &[i128]) -> Vec<i128> {
+        self.adjugate.iter().map(|row| row.iter().zip(target).map(|(a, t)| a * t).sum()).collect()
+    }
+
+    // Which of the det classes the target falls in. The basis buttons reach it
+    // with whole presses exactly when this is all zeros.
+    fn residue(&self, target: &[i128]) -> Vec<i128> {
+        self.scaled_presses(target).into_iter().map(|p| p.rem_euclid(self.det)).collect()
+    }
+}
+
+// gcd of the largest square minors of independent columns, which is how many
+// points of the grid there are per point of the lattice the columns generate
+fn lattice_index(columns: &[Vec<i128>]) -> i128 {
+    match columns.first().map_or(0, |c| c.len()) {
+        0 => 1,
+        1 => columns.iter().fold(0, |g, c| gcd(g, c[0])),
+        _ => {
+            let mut g = 0;
+            for (i, p) in columns.iter().enumerate() {
+                for q in &columns[i + 1..] {
+                    g = gcd(g, p[0] * q[1] - p[1] * q[0]);
+                }
+            }
+            g
+        }
+    }
+}
+
+// Cheapest total of the given weights of presses of some generators reaching each
+// residue, by Dijkstra over the residues (there are at most det of them)
+fn residue_costs(generators: &[(Vec<i128>, i128)], det: i128) -> HashMap<Vec<i128>, i128> {
+    let zero = vec![0; generators.first().map_or(0, |(g, _)| g.len())];
+    let mut costs: HashMap<Vec<i128>, i128> = HashMap::new();
+    let mut queue = BinaryHeap::from([Reverse((0, zero))]);
+    while let Some(Reverse((cost, residue))) = queue.pop() {
+        if costs.contains_key(&residue) {
+            continue;
+        }
+        for (g, weight) in generators {
+            let next: Vec<i128> = residue.iter().zip(g).map(|(r, g)| (r + g).rem_euclid(det)).collect();
+            if !costs.contains_key(&next) {
+                queue.push(Reverse((cost + weight, next)));
+            }
+        }
+        costs.insert(residue, cost);
+    }
+    costs
+}
+
+// Exact cheapest non-negative presses for any number of buttons: minimise costs . n
+// subject to the sum of n_k * movement_k reaching the prize.
+//
+// The prize first has to be on the lattice of points whole (possibly negative)
+// presses reach. Then the linear relaxation is solved by trying every basis.
+// Some optimal integer solution lies within m(2mD+1)^m in L1 distance of the
+// relaxed optimum, for m equations and D the largest movement (Eisenbrand &
+// Weismantel, 2018), so the buttons outside the basis are pressed at most that
+// many times in total. Those presses are searched depth first, pruned by their
+// reduced costs and by which residues mod det the remaining buttons can still
+// make up; the basis buttons then have to make up the rest exactly.
+fn solve_integer_program(movements: &[Point], prize: Point, costs: &[i128]) -> Option<Vec<i128>> {
+    let eq = independent_equations(movements, prize)?;
+    let n = movements.len();
+
+    let column = |k: usize| -> Vec<i128> { eq.rows.iter().map(|row| row[k]).collect() };
+    let mut columns: Vec<Vec<i128>> = (0..n).map(column).collect();
+    let buttons_index = lattice_index(&columns);
+    columns.push(eq.target.clone());
+    if lattice_index(&columns) != buttons_index {
         return None;
     }
 
-    // Determinants of replacement matrices
-    let det_x = m.prize.x * m.B.y - m.B.x * m.prize.y;
-    let det_y = m.A.x * m.prize.y - m.prize.x * m.A.y;
+    let mut bases: Vec<Basis> = Vec::new();
+    match eq.rows.len() {
+        0 => bases.extend(Basis::new(&eq, Vec::new())),
+        1 => bases.extend((0..n).filter_map(|i| Basis::new(&eq, vec![i]))),
+        _ => {
+            for i in 0..n {
+                bases.extend((i + 1..n).filter_map(|j| Basis::new(&eq, vec![i, j])));
+            }
+        }
+    }
 
-    // Solve for number of presses
-    let n_a = det_x / det_a;
-    let n_b = det_y / det_a;
+    // Relaxed cost and reduced costs of each feasible basis, both times its det
+    let mut best_basis: Option<(Basis, i128, Vec<i128>)> = None;
+    for basis in bases {
+        let presses = basis.scaled_presses(&eq.target);
+        if presses.iter().any(|&p| p < 0) {
+            continue;
+        }
+        let relaxed: i128 = basis.columns.iter().zip(&presses).map(|(&c, p)| costs[c] * p).sum();
+        let reduced: Vec<i128> = (0..n).map(|k| {
+            let through_basis: i128 = basis.columns.iter().zip(basis.scaled_presses(&column(k)))
+                .map(|(&c, p)| costs[c] * p)
+                .sum();
+            costs[k] * basis.det - through_basis
+        }).collect();
+        // prefer cheaper, then a basis that no other button can improve on
+        let better = match &best_basis {
+            None => true,
+            Some((old_basis, old_relaxed, old_reduced)) => {
+                let (new, old) = (relaxed * old_basis.det, old_relaxed * basis.det);
+                new < old || (new == old && old_reduced.iter().any(|&r| r < 0) && reduced.iter().all(|&r| r >= 0))
+            }
+        };
+        if better {
+            best_basis = Some((basis, relaxed, reduced));
+        }
+    }
+    let (basis, relaxed, reduced) = best_basis?;
 
-    // Check if the solution makes sense
-    if n_a >= 0 && n_b >= 0 && det_x % det_a == 0 && det_y % det_a == 0 {
-        println!("P2: Press Button A {} times, Button B {} times", n_a, n_b);
-        Some((n_a, n_b))
-    } else {
-        println!("P2: No valid combinations found.");
-        None
+    let m = eq.rows.len() as u32;
+    let largest = eq.rows.iter().flatten().map(|v| v.abs()).max().unwrap_or(0).max(1);
+    let radius = (2 * m as i128)
+        .saturating_mul(largest)
+        .saturating_add(1)
+        .saturating_pow(m)
+        .saturating_mul(m as i128);
+
+    // A button inside the basis's cone only ever uses up basis presses, and det
+    // presses of it can always be swapped for basis presses at no extra cost. So
+    // if some det of its presses or those of other such buttons add up to a
+    // residue of zero, they can be dropped. det presses in a row always contain
+    // such a run, so these buttons need fewer than det presses between them.
+    let in_cone = |k: &usize| reduced[*k] >= 0 && basis.scaled_presses(&column(*k)).iter().all(|&p| p >= 0);
+    let outside: Vec<usize> = (0..n).filter(|k| !basis.columns.contains(k) && !in_cone(k)).collect();
+    let inside: Vec<usize> = (0..n).filter(|k| !basis.columns.contains(k) && in_cone(k)).collect();
+    let free: Vec<usize> = outside.iter().chain(&inside).copied().collect();
+
+    // Cheapest way to make up each residue with the free buttons from each depth on
+    let weight = |k: usize| if reduced.iter().all(|&r| r >= 0) { reduced[k] } else { 0 };
+    let residues: Vec<HashMap<Vec<i128>, i128>> = (0..free.len())
+        .map(|depth| {
+            let generators: Vec<(Vec<i128>, i128)> = free[depth..].iter()
+                .map(|&k| (basis.residue(&column(k)), weight(k)))
+                .collect();
+            residue_costs(&generators, basis.det)
+        })
+        .collect();
+    // Fewest presses of the last free button giving each residue, and how often they repeat
+    let mut last_presses: HashMap<Vec<i128>, i128> = HashMap::new();
+    if let Some(&k) = free.last() {
+        let step = basis.residue(&column(k));
+        let mut residue = vec![0; step.len()];
+        for count in 0.. {
+            if last_presses.insert(residue.clone(), count).is_some() {
+                break;
+            }
+            residue = residue.iter().zip(&step).map(|(r, s)| (r + s).rem_euclid(basis.det)).collect();
+        }
+    }
+
+    // Nothing can beat the relaxed cost plus the cheapest way to fix the residue, rounded up
+    let needed = basis.residue(&eq.target);
+    let fix = match residues.first() {
+        Some(costs) => *costs.get(&needed)?,
+        None if needed.iter().all(|&r| r == 0) => 0,
+        None => return None,
+    };
+    let mut search = ProgramSearch {
+        eq: &eq,
+        basis: &basis,
+        costs,
+        free: &free,
+        outside: outside.len(),
+        reduced: &reduced,
+        prune: reduced.iter().all(|&r| r >= 0),
+        lower_bound: (relaxed + fix + basis.det - 1) / basis.det,
+        residues: &residues,
+        last_presses: &last_presses,
+        presses: vec![0; n],
+        best: None,
+    };
+    search.visit(0, radius, basis.det - 1, relaxed, eq.target.clone());
+    search.best.map(|(_, presses)| presses)
+}
+
+struct ProgramSearch<'a> {
+    eq: &'a Equations,
+    basis: &'a Basis,
+    costs: &'a [i128],
+    // free buttons, the first `outside` of them outside the basis's cone
+    free: &'a [usize],
+    outside: usize,
+    reduced: &'a [i128],
+    prune: bool,
+    lower_bound: i128,
+    residues: &'a [HashMap<Vec<i128>, i128>],
+    last_presses: &'a HashMap<Vec<i128>, i128>,
+    presses: Vec<i128>,
+    best: Option<(i128, Vec<i128>)>,
+}
+
+impl ProgramSearch<'_> {
+    fn done(&self) -> bool {
+        self.best.as_ref().is_some_and(|(cost, _)| *cost <= self.lower_bound)
+    }
+
+    fn beaten(&self, scaled_cost: i128) -> bool {
+        self.prune && self.best.as_ref().is_some_and(|(best, _)| scaled_cost >= best * self.basis.det)
+    }
+
+    // scaled_cost is det * (relaxed cost + reduced costs of the free presses so far),
+    // which is never more than det * the cost of any solution extending them.
+    // budget caps all the free presses, inside_budget those of buttons inside the cone.
+    fn visit(&mut self, depth: usize, budget: i128, inside_budget: i128, scaled_cost: i128, mut target: Vec<i128>) {
+        if depth == self.free.len() {
+            let scaled = self.basis.scaled_presses(&target);
+            if scaled.iter().any(|&p| p < 0 || p % self.basis.det != 0) {
+                return;
+            }
+            for (&c, p) in self.basis.columns.iter().zip(scaled) {
+                self.presses[c] = p / self.basis.det;
+            }
+            let cost: i128 = self.presses.iter().zip(self.costs).map(|(p, c)| p * c).sum();
+            if self.best.as_ref().is_none_or(|(best, _)| cost < *best) {
+                self.best = Some((cost, self.presses.clone()));
+            }
+            return;
+        }
+
+        let k = self.free[depth];
+        let inside = depth >= self.outside;
+        let budget = if inside { budget.min(inside_budget) } else { budget };
+        if depth + 1 == self.free.len() {
+            self.visit_last(k, budget, scaled_cost, target);
+            return;
+        }
+        for count in 0..=budget {
+            let scaled_cost = scaled_cost + count * self.reduced[k];
+            if self.beaten(scaled_cost) {
+                break;
+            }
+            // every button from here on only uses up basis presses
+            if inside && self.basis.scaled_presses(&target).iter().any(|&p| p < 0) {
+                break;
+            }
+            let fix = self.residues[depth + 1].get(&self.basis.residue(&target));
+            if fix.is_some_and(|&fix| !self.beaten(scaled_cost + fix)) {
+                self.presses[k] = count;
+                let inside_budget = if inside { inside_budget - count } else { inside_budget };
+                self.visit(depth + 1, budget - count, inside_budget, scaled_cost, target.clone());
+                if self.done() {
+                    break;
+                }
+            }
+            for (t, row) in target.iter_mut().zip(&self.eq.rows) {
+                *t -= row[k];
+            }
+        }
+        self.presses[k] = 0;
+    }
+
+    // The last free button's presses are pinned to one residue, repeating every
+    // period, and keeping the basis presses non-negative bounds them on each side,
+    // so the cheapest choice is at one end
+    fn visit_last(&mut self, k: usize, budget: i128, scaled_cost: i128, target: Vec<i128>) {
+        let Some(&first) = self.last_presses.get(&self.basis.residue(&target)) else {
+            return;
+        };
+        let period = self.last_presses.len() as i128;
+        let (mut low, mut high) = (0, budget);
+        let column: Vec<i128> = self.eq.rows.iter().map(|row| row[k]).collect();
+        for (have, each) in self.basis.scaled_presses(&target).into_iter().zip(self.basis.scaled_presses(&column)) {
+            // have - count * each >= 0
+            match each.signum() {
+                1 => high = high.min(floor_div(have, each)),
+                -1 => low = low.max(ceil_div(have, each)),
+                _ if have < 0 => return,
+                _ => {}
+            }
+        }
+        let count = if self.reduced[k] >= 0 {
+            low + (first - low).rem_euclid(period)
+        } else {
+            high - (high - first).rem_euclid(period)
+        };
+        if count < low || count > high || self.beaten(scaled_cost + count * self.reduced[k]) {
+            return;
+        }
+        self.presses[k] = count;
+        let target: Vec<i128> = target.iter().zip(column).map(|(t, c)| t - count * c).collect();
+        self.visit(self.free.len(), 0, 0, scaled_cost + count * self.reduced[k], target);
+        self.presses[k] = 0;
+    }
+}
+
+// Cheapest presses for a machine: Cramer's rule for the usual two buttons,
+// the integer program for any other number
+fn solve_machine(m: &Machine, costs: &[i128]) -> Option<Vec<i128>> {
+    match m.buttons.as_slice() {
+        [a, b] => solve_two_buttons(a.movement, b.movement, m.prize, costs[0], costs[1]).map(|(n_a, n_b)| vec![n_a, n_b]),
+        _ => {
+            let movements: Vec<Point> = m.buttons.iter().map(|b| b.movement).collect();
+            solve_integer_program(&movements, m.prize, costs)
+        }
+    }
+}
+
+fn describe_presses(m: &Machine, presses: &[i128]) -> String {
+    let each: Vec<String> = m.buttons.iter().zip(presses)
+        .map(|(b, n)| format!("Button {} {} times", b.label, n))
+        .collect();
+    format!("Press {}", each.join(", "))
+}
+
+// xorshift64, enough randomness to generate test machines
+struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // an all zero state would stay zero forever
+        XorShift { state: seed.max(1) }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    // uniform enough in lo..=hi for small ranges
+    fn range(&mut self, lo: i128, hi: i128) -> i128 {
+        lo + (self.next() % (hi - lo + 1) as u64) as i128
+    }
+}
+
+const DEFAULT_SEED: u64 = 0x2024_1213;
+
+// Largest movement and press count in generated machines. Movements are never
+// negative so that find_press_combinations() is an exhaustive search.
+const MAX_MOVEMENT: i128 = 12;
+const MAX_PRESSES: i128 = 20;
+
+fn random_machine(rng: &mut XorShift) -> (Point, Point, Point) {
+    let mut point = |max: i128| Point { x: rng.range(0, max), y: rng.range(0, max) };
+    let a = point(MAX_MOVEMENT);
+    let mut b = point(MAX_MOVEMENT);
+    // collinear buttons are the interesting case for the closed form
+    if rng.range(0, 2) == 0 {
+        let (num, den) = (rng.range(1, 4), rng.range(1, 4));
+        if a.x * num % den == 0 && a.y * num % den == 0 {
+            b = Point { x: a.x * num / den, y: a.y * num / den };
+        }
+    }
+    let (n_a, n_b) = (rng.range(0, MAX_PRESSES), rng.range(0, MAX_PRESSES));
+    let mut prize = Point { x: n_a * a.x + n_b * b.x, y: n_a * a.y + n_b * b.y };
+    // sometimes knock the prize off so there's no solution
+    if rng.range(0, 3) == 0 {
+        prize.x += rng.range(-1, 1);
+        prize.y += rng.range(-1, 1);
+    }
+    (a, b, prize)
+}
+
+// Cheapest cost from each solver, or a description of why the closed form's answer is wrong
+fn compare_solvers(a: Point, b: Point, prize: Point, cost_a: i128, cost_b: i128) -> Option<String> {
+    let brute = find_press_combinations(a, b, prize).iter().map(|(n_a, n_b)| n_a * cost_a + n_b * cost_b).min();
+    let closed = solve_two_buttons(a, b, prize, cost_a, cost_b);
+    if let Some((n_a, n_b)) = closed {
+        let reached = Point { x: n_a * a.x + n_b * b.x, y: n_a * a.y + n_b * b.y };
+        if n_a < 0 || n_b < 0 || reached != prize {
+            return Some(format!("closed form presses ({}, {}) don't reach the prize", n_a, n_b));
+        }
+    }
+    let closed = closed.map(|(n_a, n_b)| n_a * cost_a + n_b * cost_b);
+    if brute != closed {
+        return Some(format!("brute force costs {:?}, closed form costs {:?}", brute, closed));
+    }
+    None
+}
+
+// Simpler machines close to the given one: each coordinate moved towards zero,
+// everything divided by a common factor, or the prize one press nearer
+fn shrink_candidates(v: [i128; 6]) -> Vec<[i128; 6]> {
+    let mut candidates = Vec::new();
+    let common = v.iter().fold(0, |g, &x| gcd(g, x));
+    if common > 1 {
+        candidates.push(v.map(|x| x / common));
+    }
+    for (dx, dy) in [(v[0], v[1]), (v[2], v[3])] {
+        candidates.push([v[0], v[1], v[2], v[3], v[4] - dx, v[5] - dy]);
+    }
+    for i in 0..v.len() {
+        for smaller in [0, v[i] / 2, v[i] - v[i].signum()] {
+            if smaller != v[i] {
+                let mut c = v;
+                c[i] = smaller;
+                candidates.push(c);
+            }
+        }
+    }
+    // only strictly smaller machines, so shrinking always finishes
+    let size = |c: &[i128; 6]| c.iter().map(|x| x.abs()).sum::<i128>();
+    candidates.retain(|c| size(c) < size(&v));
+    candidates
+}
+
+// Keep taking the first simpler machine the solvers still disagree on
+fn shrink(mut values: [i128; 6], fails: impl Fn(&[i128; 6]) -> bool) -> [i128; 6] {
+    while let Some(smaller) = shrink_candidates(values).into_iter().find(|c| fails(c)) {
+        values = smaller;
     }
+    values
+}
+
+fn differential_check(count: usize, seed: u64, cost_a: i128, cost_b: i128) {
+    let unpack = |v: &[i128; 6]| (Point { x: v[0], y: v[1] }, Point { x: v[2], y: v[3] }, Point { x: v[4], y: v[5] });
+    let fails = |v: &[i128; 6]| {
+        let (a, b, prize) = unpack(v);
+        compare_solvers(a, b, prize, cost_a, cost_b).is_some()
+    };
+
+    let mut rng = XorShift::new(seed);
+    let mut disagreements = 0;
+    for i in 0..count {
+        let (a, b, prize) = random_machine(&mut rng);
+        if compare_solvers(a, b, prize, cost_a, cost_b).is_none() {
+            continue;
+        }
+        disagreements += 1;
+        let (a, b, prize) = unpack(&shrink([a.x, a.y, b.x, b.y, prize.x, prize.y], fails));
+        let reason = compare_solvers(a, b, prize, cost_a, cost_b).unwrap_or_default();
+        println!("Machine {} disagrees: {}", i, reason);
+        println!("  Button A: X{:+}, Y{:+}", a.x, a.y);
+        println!("  Button B: X{:+}, Y{:+}", b.x, b.y);
+        println!("  Prize: X={}, Y={}", prize.x, prize.y);
+    }
+    println!("Checked {} machines with seed {}, {} disagreements", count, seed, disagreements);
 }
 
 fn main() -> io::Result<()> {
 
+    // Options:
+    //   --costs <A=3,B=1,...>  tokens per press for each button label
+    //   --differential <n>     compare the brute force and closed form solvers on n
+    //                          random machines instead of solving the input
+    //   --seed <n>             seed for --differential
+    let args: Vec<String> = env::args().skip(1).collect();
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let option = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let costs = match option("--costs") {
+        Some(list) => parse_costs(list).map_err(invalid)?,
+        None => default_costs(),
+    };
+
+    if let Some(count) = option("--differential") {
+        let count: usize = count.parse().map_err(|_| invalid(format!("--differential needs a number, not '{}'", count)))?;
+        let seed: u64 = match option("--seed") {
+            Some(seed) => seed.parse().map_err(|_| invalid(format!("--seed needs a number, not '{}'", seed)))?,
+            None => DEFAULT_SEED,
+        };
+        let cost_a = *costs.get("A").ok_or_else(|| invalid("--costs needs a cost for A".to_string()))?;
+        let cost_b = *costs.get("B").ok_or_else(|| invalid("--costs needs a cost for B".to_string()))?;
+        differential_check(count, seed, cost_a, cost_b);
+        return Ok(());
+    }
+
     // Open the file
     let path = Path::new("input");
     //let path = Path::new("sample");
-    let file = File::open(&path)?;
+    let file = File::open(path)?;
     let reader = io::BufReader::new(file);
 
-    let mut part1_answer: isize = 0;
-    let mut part2_answer: isize = 0;
+    let mut part1_answer: i128 = 0;
+    let mut part2_answer: i128 = 0;
 
-    let mut lines_buffer: Vec<String> = Vec::new();
-    let mut machines: Vec<Machine> = Vec::new();
     // Process each line
-    for line in reader.lines() {
-        let line = line?;
-        if line.trim().is_empty() {
-            if let Some(machine) = parse_claw_machine(&lines_buffer) {
-               machines.push(machine);
-            }
-            lines_buffer.clear();
-        }
-        else {
-            lines_buffer.push(line);
-        }
-    }
-    if !lines_buffer.is_empty() {
-        if let Some(machine) = parse_claw_machine(&lines_buffer) {
-            machines.push(machine);
-        }
+    let lines: Vec<String> = reader.lines().collect::<Result<_, _>>()?;
+    let (machines, errors) = parse_machines(&lines);
+    for e in &errors {
+        println!("Skipping lines {}-{}: {}", e.first_line, e.last_line, e.message);
     }
 
     for mut m in machines {
         println!("{:?}", m);
+        let button_costs = match machine_costs(&m, &costs) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("Skipping machine: {}", e);
+                continue;
+            }
+        };
+        let cost_of = |presses: &[i128]| -> i128 { presses.iter().zip(&button_costs).map(|(p, c)| p * c).sum() };
+
         // part 1
-        let combinations = find_press_combinations(m);
-        let mut lowest_cost: isize = isize::MAX;
-        for c in &combinations {
-            let cost = (c.0 * 3) + c.1;
-            if cost < lowest_cost {
-                lowest_cost = cost;
+        if let [a, b] = m.buttons.as_slice() {
+            println!("Finding presses for target: ({}, {})", m.prize.x, m.prize.y);
+            let combinations = find_press_combinations(a.movement, b.movement, m.prize);
+            if combinations.is_empty() {
+                println!("P1: No valid combinations found.");
+            }
+            let mut lowest_cost: i128 = i128::MAX;
+            for c in &combinations {
+                println!("P1: Press Button {} {} times, Button {} {} times", a.label, c.0, b.label, c.1);
+                let cost = cost_of(&[c.0, c.1]);
+                if cost < lowest_cost {
+                    lowest_cost = cost;
+                }
+            }
+            if !combinations.is_empty() && lowest_cost < i128::MAX {
+                part1_answer += lowest_cost;
+            }
+        } else {
+            match solve_machine(&m, &button_costs) {
+                Some(presses) => {
+                    println!("P1: {}", describe_presses(&m, &presses));
+                    part1_answer += cost_of(&presses);
+                }
+                None => println!("P1: No valid combinations found."),
             }
-        }
-        if !combinations.is_empty() && lowest_cost < isize::MAX {
-            part1_answer += lowest_cost;
         }
 
         // part 2
         m.prize.x += 10000000000000;
         m.prize.y += 10000000000000;
-        if let Some(buttons) = solve_machine(m) {
-            let cost = (buttons.0 * 3) + buttons.1;
-            part2_answer += cost;
+        match solve_machine(&m, &button_costs) {
+            Some(presses) => {
+                println!("P2: {}", describe_presses(&m, &presses));
+                part2_answer += cost_of(&presses);
+            }
+            None => println!("P2: No valid combinations found."),
         }
     }
     println!("Part1: {:?}", part1_answer);
@@ -180,3 +890,95 @@ fn main() -> io::Result<()> {
     Ok(())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(buttons: &[(&str, i128, i128)], prize: (i128, i128)) -> Machine {
+        Machine {
+            buttons: buttons.iter()
+                .map(|&(label, x, y)| Button { label: label.to_string(), movement: Point { x, y } })
+                .collect(),
+            prize: Point { x: prize.0, y: prize.1 },
+        }
+    }
+
+    // Fractional presses reach the first prize but whole ones can't: every movement
+    // is even along X, so the prize has to be too
+    #[test]
+    fn four_buttons_off_the_lattice() {
+        let buttons = [("A", 94, 34), ("B", 22, 68), ("C", 18, 12), ("D", 52, 88)];
+        let costs = [3, 1, 2, 5];
+        assert_eq!(solve_machine(&machine(&buttons, (8401, 5400)), &costs), None);
+        assert_eq!(solve_machine(&machine(&buttons, (8400, 5400)), &costs), Some(vec![20, 6, 352, 1]));
+    }
+
+    // Cheapest cost by trying every press count up to what the prize allows.
+    // Movements are never negative, so nothing past that can help.
+    fn enumerate(movements: &[Point], prize: Point, costs: &[i128]) -> Option<i128> {
+        let Some((m, rest)) = movements.split_first() else {
+            return if prize == (Point { x: 0, y: 0 }) { Some(0) } else { None };
+        };
+        let most = if m.x > 0 { prize.x / m.x } else if m.y > 0 { prize.y / m.y } else { 0 };
+        (0..=most)
+            .filter_map(|n| {
+                let left = Point { x: prize.x - n * m.x, y: prize.y - n * m.y };
+                if left.x < 0 || left.y < 0 {
+                    return None;
+                }
+                enumerate(rest, left, &costs[1..]).map(|cost| cost + n * costs[0])
+            })
+            .min()
+    }
+
+    #[test]
+    fn integer_program_agrees_with_enumeration() {
+        let mut rng = XorShift::new(DEFAULT_SEED);
+        let mut range = |lo: i128, hi: i128| rng.range(lo, hi);
+        for _ in 0..2000 {
+            let n = range(3, 4) as usize;
+            let scale = range(1, 3);
+            let movements: Vec<Point> = (0..n).map(|_| Point { x: scale * range(0, 5), y: scale * range(0, 5) }).collect();
+            let costs: Vec<i128> = (0..n).map(|_| range(0, 5)).collect();
+            let mut prize = Point { x: 0, y: 0 };
+            for m in &movements {
+                let presses = range(0, 4);
+                prize = Point { x: prize.x + presses * m.x, y: prize.y + presses * m.y };
+            }
+            match range(0, 3) {
+                // usually off the lattice when the movements share a factor
+                0 => prize.x += 1,
+                // maybe reachable only with negative presses
+                1 => prize = Point { x: prize.x + range(-3, 3), y: prize.y + range(-3, 3) },
+                _ => {}
+            }
+
+            let expected = enumerate(&movements, prize, &costs);
+            let solved = solve_integer_program(&movements, prize, &costs);
+            if let Some(presses) = &solved {
+                let reached = movements.iter().zip(presses)
+                    .fold(Point { x: 0, y: 0 }, |p, (m, n)| Point { x: p.x + n * m.x, y: p.y + n * m.y });
+                assert!(presses.iter().all(|&p| p >= 0) && reached == prize,
+                    "{:?} don't reach {:?} with {:?}", presses, prize, movements);
+            }
+            let cost = solved.map(|presses| presses.iter().zip(&costs).map(|(p, c)| p * c).sum::<i128>());
+            assert_eq!(cost, expected, "movements {:?}, prize {:?}, costs {:?}", movements, prize, costs);
+        }
+    }
+
+    #[test]
+    fn closed_form_agrees_with_brute_force() {
+        for seed in [1, 2, DEFAULT_SEED] {
+            let mut rng = XorShift::new(seed);
+            for _ in 0..2000 {
+                let (a, b, prize) = random_machine(&mut rng);
+                let fails = |v: &[i128; 6]| {
+                    compare_solvers(Point { x: v[0], y: v[1] }, Point { x: v[2], y: v[3] }, Point { x: v[4], y: v[5] }, 3, 1).is_some()
+                };
+                assert_eq!(compare_solvers(a, b, prize, 3, 1), None,
+                    "seed {}, shrunk to buttons and prize {:?}", seed, shrink([a.x, a.y, b.x, b.y, prize.x, prize.y], fails));
+            }
+        }
+    }
+}
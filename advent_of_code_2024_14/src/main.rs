@@ -1,7 +1,8 @@
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::env;
 //use regex::Regex;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -16,16 +17,28 @@ struct Robot {
     vel: Point,
 }
 
-fn parse_point(s: &str, prefix: &str) -> Option<Point> {
-    if let Some(coords) = s.strip_prefix(prefix) {
-        let parts: Vec<&str> = coords.split(',').collect();
-        if parts.len() == 2 {
-            let x = parts[0].parse::<isize>().unwrap_or(0);
-            let y = parts[1].parse::<isize>().unwrap_or(0);
-            return Some(Point{ x, y });
-        }
+fn parse_point(s: &str, prefix: &str) -> Result<Point, String> {
+    let coords = s.strip_prefix(prefix).ok_or_else(|| format!("expected '{}' in '{}'", prefix, s))?;
+    let (x, y) = coords.split_once(',').ok_or_else(|| format!("expected x,y in '{}'", s))?;
+    let number = |n: &str| n.parse::<isize>().map_err(|_| format!("bad number '{}' in '{}'", n, s));
+    Ok(Point{ x: number(x)?, y: number(y)? })
+}
+
+// "101,103", both sides at least 1
+fn parse_size(s: &str) -> Result<Point, String> {
+    let size = parse_point(s, "")?;
+    if size.x < 1 || size.y < 1 {
+        return Err(format!("size '{}' needs to be at least 1,1", s));
+    }
+    Ok(size)
+}
+
+// "p=0,4 v=3,-3"
+fn parse_robot(line: &str) -> Result<Robot, String> {
+    match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        [p, v] => Ok(Robot{ pos: parse_point(p, "p=")?, vel: parse_point(v, "v=")? }),
+        _ => Err(format!("expected 'p=x,y v=x,y' in '{}'", line)),
     }
-    None
 }
 
 fn predict_position( r: &Robot, time: isize, space: &Point ) -> Point {
@@ -35,7 +48,7 @@ fn predict_position( r: &Robot, time: isize, space: &Point ) -> Point {
     // euclidean_modulo
     let new_x = (((r.pos.x + (r.vel.x * time)) % max_x) + max_x) % max_x;
     let new_y = (((r.pos.y + (r.vel.y * time)) % max_y) + max_y) % max_y;
-    return Point{ x: new_x, y: new_y };
+    Point{ x: new_x, y: new_y }
 }
 
 fn quadrant( p: &Point, space: &Point ) -> Option<usize> {
@@ -62,73 +75,139 @@ fn quadrant( p: &Point, space: &Point ) -> Option<usize> {
     None
 }
 
-fn render_at (robots: &Vec<Robot>, space: &Point, time: isize) {
+fn render_at (robots: &[Robot], space: &Point, time: isize) {
     println!("Robot Map at {} seconds", time);
 
-    let mut robot_positions: HashMap<Point, usize> = HashMap::new();
-    for r in robots {
-        let p = predict_position(&r, time, space);
-        *robot_positions.entry(p).or_insert(0) += 1;
-    }
+    let robot_positions: HashSet<Point> = robots.iter().map(|r| predict_position(r, time, space)).collect();
 
     for y in 0..space.y {
         for x in 0..space.x {
-            let p: Point = Point{x: x, y: y};
-            if robot_positions.contains_key(&p) {
+            let p: Point = Point{ x, y };
+            if robot_positions.contains(&p) {
                 print!("#");
             }
             else {
                 print!(" ");
             }
         }
-        println!("");
+        println!();
+    }
+}
+
+// Returns (g, x, y) with a*x + b*y = g = gcd(a, b)
+fn extended_gcd(a: isize, b: isize) -> (isize, isize, isize) {
+    if b == 0 {
+        (a, 1, 0)
+    } else {
+        let (g, x, y) = extended_gcd(b, a % b);
+        (g, y, x - (a / b) * y)
     }
 }
 
-fn robots_in_distinct_positions (robots: &Vec<Robot>, space: &Point, time: isize) -> bool {
+// Combine t = a1 (mod m1) and t = a2 (mod m2), where m1 and m2 needn't be coprime.
+// Returns (t, lcm) with t the only solution in 0..lcm, or None if the two can't both hold.
+fn chinese_remainder(a1: isize, m1: isize, a2: isize, m2: isize) -> Option<(isize, isize)> {
+    let (g, p, _) = extended_gcd(m1, m2);
+    if (a2 - a1) % g != 0 {
+        return None;
+    }
+    let lcm = m1 / g * m2;
+    // m1 * p = g (mod m2), so stepping a1 by m1 * k reaches a2 for k = p * (a2 - a1) / g
+    let k = (((a2 - a1) / g) % (m2 / g) * p).rem_euclid(m2 / g);
+    Some(((a1 + m1 * k).rem_euclid(lcm), lcm))
+}
 
-    let mut robot_positions: HashMap<Point, usize> = HashMap::new();
-    for r in robots {
-        let p = predict_position(&r, time, space);
-        if robot_positions.contains_key(&p) {
-            return false;
+// Positions along one axis repeat every `size` seconds. Returns the offsets in 0..size
+// where the robots are most bunched up along that axis, measured by variance.
+fn tightest_offsets(robots: &[Robot], size: isize, axis: fn(&Point) -> isize) -> Vec<isize> {
+    let n = robots.len() as i128;
+    let mut best: Vec<isize> = Vec::new();
+    let mut best_spread = i128::MAX;
+    for t in 0..size {
+        let (mut sum, mut sum_sq) = (0i128, 0i128);
+        for r in robots {
+            let p = (axis(&r.pos) + axis(&r.vel) * t).rem_euclid(size) as i128;
+            sum += p;
+            sum_sq += p * p;
+        }
+        // n^2 * variance, which stays an exact integer
+        let spread = n * sum_sq - sum * sum;
+        if spread < best_spread {
+            best_spread = spread;
+            best.clear();
+        }
+        if spread == best_spread {
+            best.push(t);
         }
-        robot_positions.insert(p, 1);
     }
-    if robots.len() == robot_positions.len() {
-        return true;
+    best
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Picture {
+    // the picture appears at this time and again every period seconds
+    Found { time: isize, period: isize },
+    // several offsets tie for the tightest bunching along an axis
+    Ambiguous { axis: char, offsets: Vec<isize> },
+    // the tightest x and y offsets never happen at the same time
+    Inconsistent { x_offset: isize, y_offset: isize },
+}
+
+// The picture is where the robots bunch up along both axes at once. x positions repeat
+// every width seconds and y positions every height, so find the best offset for each
+// axis separately and combine them with the Chinese remainder theorem.
+fn find_picture(robots: &[Robot], space: &Point) -> Picture {
+    let x_offsets = tightest_offsets(robots, space.x, |p| p.x);
+    let y_offsets = tightest_offsets(robots, space.y, |p| p.y);
+    let (x_offset, y_offset) = match (x_offsets.as_slice(), y_offsets.as_slice()) {
+        ([x], [y]) => (*x, *y),
+        ([_], _) => return Picture::Ambiguous { axis: 'y', offsets: y_offsets },
+        _ => return Picture::Ambiguous { axis: 'x', offsets: x_offsets },
+    };
+    match chinese_remainder(x_offset, space.x, y_offset, space.y) {
+        // the robots start in the picture, so the next time it appears is a full period later
+        Some((0, period)) => Picture::Found { time: period, period },
+        Some((time, period)) => Picture::Found { time, period },
+        None => Picture::Inconsistent { x_offset, y_offset },
     }
-    false
 }
 
 fn main() -> io::Result<()> {
 
+    // Options:
+    //   --size <w>,<h>  dimensions of the space, instead of the puzzle's sizes
+    let args: Vec<String> = env::args().skip(1).collect();
+    let size = args.iter().position(|a| a == "--size").and_then(|i| args.get(i + 1));
+
     // Open the file
     //let filename = "sample";
     let filename = "input";
     //let path = Path::new("input");
     let path = Path::new(filename);
-    let file = File::open(&path)?;
+    let file = File::open(path)?;
     let reader = io::BufReader::new(file);
 
 
-    let space = if filename == "input" { Point{ x: 101, y: 103 } } else { Point{ x: 11, y: 7 } };
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let space = match size {
+        Some(size) => parse_size(size).map_err(invalid)?,
+        None if filename == "input" => Point{ x: 101, y: 103 },
+        None => Point{ x: 11, y: 7 },
+    };
 
     let mut robots: Vec<Robot> = Vec::new();
     // Process each line
-    for line in reader.lines() {
+    for (line_idx, line) in reader.lines().enumerate() {
         let line = line?;
-        let details: Vec<&str> = line.split_whitespace().collect();
-        if let Some(p) = parse_point(details[0], "p=") {
-            if let Some(v) = parse_point(details[1], "v=") {
-                robots.push(Robot{ pos: p, vel: v });
-            }
+        if line.trim().is_empty() {
+            continue;
         }
-        
+        let robot = parse_robot(&line).map_err(|e| invalid(format!("line {}: {}", line_idx + 1, e)))?;
+        robots.push(robot);
     }
     let mut quadrant_count: HashMap<usize,usize> = HashMap::new();
     for r in &robots {
-        let p = predict_position(&r, 100, &space);
+        let p = predict_position(r, 100, &space);
         println!("Robot {:?} at pos {:?} after 100 seconds", r, p);
         if let Some(q) = quadrant(&p, &space) {
             println!("In Quadrant {}", q);
@@ -141,16 +220,62 @@ fn main() -> io::Result<()> {
 
     let part1_answer: usize = quadrant_count.values().cloned().product();
     println!("Quadrants: {:?}", quadrant_count);
-    
-    let mut i: isize = 1;
-    while !robots_in_distinct_positions(&robots, &space, i) && i < 100000 {
-        i += 1;
-    }
-    render_at(&robots, &space, i);
-    let part2_answer = i;
+
+    let part2_answer = match find_picture(&robots, &space) {
+        Picture::Found { time, period } => {
+            render_at(&robots, &space, time);
+            println!("The picture repeats every {} seconds", period);
+            Some(time)
+        }
+        Picture::Ambiguous { axis, offsets } => {
+            println!("No unique answer: offsets {:?} tie for the tightest {} positions", offsets, axis);
+            None
+        }
+        Picture::Inconsistent { x_offset, y_offset } => {
+            println!("No answer: x offset {} (mod {}) and y offset {} (mod {}) never coincide",
+                x_offset, space.x, y_offset, space.y);
+            None
+        }
+    };
     println!("Part1: {:?}", part1_answer);
-    println!("Part2: {:?}", part2_answer);
+    println!("Part2: {}", part2_answer.map_or("none".to_string(), |t| t.to_string()));
 
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn robot(px: isize, py: isize, vx: isize, vy: isize) -> Robot {
+        Robot{ pos: Point{ x: px, y: py }, vel: Point{ x: vx, y: vy } }
+    }
+
+    // Robots that all line up along x at time x_at and along y at time y_at, and at
+    // no other time in the first 12 and 8 seconds
+    fn lining_up(x_at: isize, y_at: isize) -> Vec<Robot> {
+        (1..=4).map(|v| robot((-v * x_at).rem_euclid(12), (-v * y_at).rem_euclid(8), v, v)).collect()
+    }
+
+    // 12 and 8 share a factor of 4, so only offsets that agree mod 4 combine
+    #[test]
+    fn picture_on_a_non_coprime_space() {
+        let space = Point{ x: 12, y: 8 };
+        assert_eq!(find_picture(&lining_up(1, 5), &space), Picture::Found { time: 13, period: 24 });
+        assert_eq!(find_picture(&lining_up(1, 2), &space), Picture::Inconsistent { x_offset: 1, y_offset: 2 });
+
+        // moving together along y, the robots are just as spread out there at every time
+        let together: Vec<Robot> = lining_up(1, 0).into_iter().map(|r| Robot{ vel: Point{ x: r.vel.x, y: 3 }, ..r }).collect();
+        assert!(matches!(find_picture(&together, &space), Picture::Ambiguous { axis: 'y', offsets } if offsets.len() == 8));
+        let still = [robot(3, 3, 0, 0), robot(7, 1, 0, 0)];
+        assert!(matches!(find_picture(&still, &space), Picture::Ambiguous { axis: 'x', offsets } if offsets.len() == 12));
+    }
+
+    #[test]
+    fn sizes_that_are_not_numbers() {
+        assert_eq!(parse_size("12,8"), Ok(Point{ x: 12, y: 8 }));
+        assert!(parse_size("101,abc").unwrap_err().contains("bad number 'abc'"));
+        assert!(parse_size("0,8").is_err());
+        assert!(parse_robot("p=1,x v=2,3").is_err());
+    }
+}